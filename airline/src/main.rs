@@ -1,95 +1,82 @@
-use actix::{Actor, Context, Handler};
-use helpers::entity_main::run_entity;
-use helpers::TransactionMessage;
-use std::collections::HashMap;
-use tokio::net::TcpListener;
-
-enum TransactionState {
-    Accepted { client: String },
-    Abort,
-    Commit,
-}
+use helpers::alglobo_transaction::AlgloboTransaction;
+use helpers::args::Args;
+use helpers::entity_main::{run_entity, EntityOptions};
+use helpers::event::AIRLINE_ENTITY;
+use helpers::participant::Participant;
+use helpers::reservations::Reservations;
 
 struct Airline {
-    reservations: u32,
-    transaction_log: HashMap<u32, TransactionState>,
+    reservations: Reservations,
 }
 
 impl Airline {
     pub fn new() -> Self {
         Self {
-            reservations: 0,
-            transaction_log: HashMap::new(),
+            reservations: Reservations::default(),
         }
     }
 }
-impl Actor for Airline {
-    type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
-        println!("[AIRLINE] Iniciado");
+impl Participant for Airline {
+    const NAME: &'static str = "AIRLINE";
+    const ENTITY: u8 = AIRLINE_ENTITY;
+
+    fn on_prepare(&mut self, transaction: &AlgloboTransaction) -> bool {
+        if transaction.client == "falla_airline" {
+            return false;
+        }
+        if !self.reservations.prepare() {
+            println!(
+                "Rechazando la transacción {}: no se pueden registrar más reservas",
+                transaction.id
+            );
+            return false;
+        }
+        true
     }
 
-    fn stopped(&mut self, _ctx: &mut Context<Self>) {
-        println!("[AIRLINE] Detenido");
+    fn on_commit(&mut self, transaction: &AlgloboTransaction, _timestamp_ms: u64) {
+        println!(
+            "Guardando reserva nro {} para {}",
+            self.reservations.commit(),
+            transaction.client
+        );
     }
-}
 
-impl Handler<TransactionMessage> for Airline {
-    type Result = Result<Option<bool>, std::io::Error>;
+    fn on_abort(&mut self, _transaction: &AlgloboTransaction) {
+        self.reservations.abort();
+    }
 
-    fn handle(&mut self, msg: TransactionMessage, _ctx: &mut Context<Self>) -> Self::Result {
-        println!("[Airline] handle: {:?}", msg);
-        match msg {
-            TransactionMessage::Prepare { transaction } => {
-                if self.transaction_log.contains_key(&transaction.id) {
-                    // Transaction is already in the log, so it was already prepared.
-                    return Ok(Some(true));
-                }
+    fn on_prepare_cancellation(&mut self, transaction: &AlgloboTransaction) -> bool {
+        if !self.reservations.prepare_cancellation() {
+            println!(
+                "Rechazando la cancelación de la transacción {}: no quedan reservas",
+                transaction.id
+            );
+            return false;
+        }
+        true
+    }
 
-                if transaction.client == "falla_airline" {
-                    return Ok(Some(false));
-                }
-                self.transaction_log.insert(
-                    transaction.id,
-                    TransactionState::Accepted {
-                        client: transaction.client,
-                    },
-                );
+    fn on_cancel(&mut self, transaction: &AlgloboTransaction, _timestamp_ms: u64) {
+        println!(
+            "Liberando el asiento de la transacción {} de {}",
+            transaction.id, transaction.client
+        );
+        self.reservations.cancel();
+    }
 
-                return Ok(Some(true));
-            }
-            TransactionMessage::Abort { transaction_id } => {
-                self.transaction_log
-                    .insert(transaction_id, TransactionState::Abort);
-            }
-            TransactionMessage::Commit { transaction_id } => {
-                match self.transaction_log.get(&transaction_id) {
-                    Some(TransactionState::Accepted { client }) => {
-                        println!(
-                            "Guardando reserva nro {} para {client}",
-                            self.reservations + 1
-                        );
-                        self.reservations += 1;
-                        self.transaction_log
-                            .insert(transaction_id, TransactionState::Commit);
-                    }
-                    Some(TransactionState::Commit) => { /* Already finished */ }
-                    Some(TransactionState::Abort) => { /* Already finished */ }
-                    _ => {}
-                }
-            }
-            _ => {}
-        }
+    fn on_abort_cancellation(&mut self, _transaction: &AlgloboTransaction) {
+        self.reservations.abort_cancellation();
+    }
 
-        Ok(Some(true))
+    fn inventory(&self) -> String {
+        self.reservations.to_string()
     }
 }
 
 #[actix_rt::main]
 async fn main() {
-    let listener = TcpListener::bind("0.0.0.0:9998")
-        .await
-        .expect("Could not open port 9998");
-    run_entity(listener, Airline::new()).await;
+    let args = Args::from_env();
+    run_entity(9998, Airline::new(), EntityOptions::from_args(&args, 9898)).await;
 }
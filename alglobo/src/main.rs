@@ -1,4 +1,3 @@
-mod leader_election;
 mod output_logger;
 mod payments_queue;
 mod replication;
@@ -9,9 +8,15 @@ use std::{
     time::{Duration, SystemTime},
 };
 
-use actix_rt::net::TcpStream;
-use helpers::{event::Event, event_protocol::EventProtocol};
-use leader_election::{bully::BullyLeaderElection, leader_election_trait::LeaderElection};
+use helpers::{
+    alglobo_transaction::AlgloboTransaction,
+    event::{
+        entity_name, Event, EventKind, EventSource, AIRLINE_ENTITY, BANK_ENTITY, HOTEL_ENTITY,
+    },
+    leader_election::{bully::BullyLeaderElection, leader_election_trait::LeaderElection},
+    protocol::DecisionOutcome,
+    stats_client::{StatsClient, STATS_BUFFER_CAPACITY, STATS_FLUSH_TIMEOUT},
+};
 use output_logger::OutputLogger;
 use payments_queue::PaymentsQueue;
 use replication::Replication;
@@ -50,14 +55,27 @@ async fn replica_main(
     let mut payments_queue =
         PaymentsQueue::new("./payments.csv", "./processed.csv", "./failed.csv")
             .expect("Could not load payments file");
-    let mut logger = OutputLogger::new("./failed.csv".into(), "./processed.csv".into())
-        .expect("Cannot start transaction logger");
+    let mut logger = OutputLogger::new(
+        "./failed.csv".into(),
+        "./processed.csv".into(),
+        "./reconciliation.csv".into(),
+    )
+    .expect("Cannot start transaction logger");
 
-    let mut hotel = TransactionalEntity::new(HOTEL_HOST).await?;
-    let mut airline = TransactionalEntity::new(AIRLINE_HOST).await?;
-    let mut bank = TransactionalEntity::new(BANK_HOST).await?;
-    let socket_event = TcpStream::connect(STATS_HOST).await?;
-    let mut event_protocol = EventProtocol::new(socket_event);
+    let source = EventSource::Replica(manager.get_current_id());
+    let mut hotel = TransactionalEntity::new("hotel", HOTEL_ENTITY, HOTEL_HOST, source).await?;
+    let mut airline =
+        TransactionalEntity::new("airline", AIRLINE_ENTITY, AIRLINE_HOST, source).await?;
+    let mut bank = TransactionalEntity::new("bank", BANK_ENTITY, BANK_HOST, source).await?;
+    // Stats are not mission-critical, payments go on while it is down.
+    let stats = StatsClient::new(STATS_HOST, STATS_BUFFER_CAPACITY);
+    stats.send(Event::new(
+        source,
+        0,
+        EventKind::LeaderChanged {
+            leader: manager.get_current_id(),
+        },
+    ));
 
     while let Some(tx) = payments_queue.pop() {
         if !manager.is_leader() {
@@ -67,43 +85,142 @@ async fn replica_main(
 
         std::thread::sleep(Duration::from_millis(3000));
         let payment_time = SystemTime::now();
-        if !hotel.create_transaction(&tx).await {
-            println!("Hotel did not like transaction {}", tx.id);
-            logger.log_failed(&tx);
-            continue;
-        }
-        if !airline.create_transaction(&tx).await {
-            println!("Airline did not like transaction {}", tx.id);
-            hotel.abort(tx.id).await;
-            logger.log_failed(&tx);
-            continue;
+        stats.send(Event::new(source, tx.id, EventKind::PaymentStarted));
+        let result = process_payment(
+            &tx,
+            &mut hotel,
+            &mut airline,
+            &mut bank,
+            &mut logger,
+            &stats,
+            source,
+        )
+        .await;
+        for entity in [&mut hotel, &mut airline, &mut bank] {
+            for event in entity.take_phase_durations() {
+                stats.send(event);
+            }
         }
-        if !bank.create_transaction(&tx).await {
-            println!("Bank did not like transaction {}", tx.id);
-            hotel.abort(tx.id).await;
-            airline.abort(tx.id).await;
-            logger.log_failed(&tx);
-            continue;
+        match result {
+            PaymentOutcome::Committed => {}
+            PaymentOutcome::Rejected(entity) => {
+                stats.send(Event::new(
+                    source,
+                    tx.id,
+                    EventKind::PaymentFailed {
+                        entity,
+                        reason: format!("rejected by the {}", entity_name(entity)),
+                    },
+                ));
+                continue;
+            }
+            PaymentOutcome::Inconsistent(entity) => {
+                println!(
+                    "Transaction {} was not committed everywhere, it needs reconciliation",
+                    tx.id
+                );
+                stats.send(Event::new(
+                    source,
+                    tx.id,
+                    EventKind::PaymentFailed {
+                        entity,
+                        reason: format!("commit not applied by the {}", entity_name(entity)),
+                    },
+                ));
+                logger.log_failed(&tx);
+                continue;
+            }
         }
 
-        hotel.commit(tx.id).await;
-        airline.commit(tx.id).await;
-        bank.commit(tx.id).await;
-
         println!("Transaction {} approved", tx.id);
         let new_sys_time = SystemTime::now();
         let difference = new_sys_time
             .duration_since(payment_time)
             .expect("Clock Error")
             .as_millis();
-        event_protocol
-            .send_event(Event::PaymentSuccess {
+        stats.send(Event::new(
+            source,
+            tx.id,
+            EventKind::PaymentSuccess {
                 duration: difference as u32,
-            })
-            .await;
+            },
+        ));
         logger.log_success(&tx);
     }
     println!("All payments have been processed");
+    if logger.reconciliation_count() > 0 {
+        println!(
+            "{} decisions were not applied, see ./reconciliation.csv",
+            logger.reconciliation_count()
+        );
+    }
+    if !stats.flush(STATS_FLUSH_TIMEOUT).await {
+        println!("Some events could not be sent to stats");
+    }
     manager.graceful_quit();
     Ok(())
 }
+
+/// How a payment ended, with the id of the entity responsible when it was not
+/// committed.
+enum PaymentOutcome {
+    Committed,
+    /// The entity rejected the payment, so it was aborted everywhere.
+    Rejected(u8),
+    /// The entity did not apply the commit, so the books do not agree and the
+    /// payment must be reconciled by hand.
+    Inconsistent(u8),
+}
+
+/// Runs the two phase commit of `tx` with every entity, reporting each step
+/// to stats.
+async fn process_payment(
+    tx: &AlgloboTransaction,
+    hotel: &mut TransactionalEntity,
+    airline: &mut TransactionalEntity,
+    bank: &mut TransactionalEntity,
+    logger: &mut OutputLogger,
+    stats: &StatsClient,
+    source: EventSource,
+) -> PaymentOutcome {
+    let aborted = Event::new(source, tx.id, EventKind::PaymentAborted);
+    if !hotel.create_transaction(tx).await {
+        println!("Hotel did not like transaction {}", tx.id);
+        stats.send(aborted);
+        logger.log_failed(tx);
+        return PaymentOutcome::Rejected(hotel.entity());
+    }
+    if !airline.create_transaction(tx).await {
+        println!("Airline did not like transaction {}", tx.id);
+        stats.send(aborted);
+        let outcome = hotel.abort(tx.id).await;
+        logger.log_decision(hotel.name(), tx.id, "abort", outcome);
+        logger.log_failed(tx);
+        return PaymentOutcome::Rejected(airline.entity());
+    }
+    if !bank.create_transaction(tx).await {
+        println!("Bank did not like transaction {}", tx.id);
+        stats.send(aborted);
+        for entity in [hotel, airline] {
+            let outcome = entity.abort(tx.id).await;
+            logger.log_decision(entity.name(), tx.id, "abort", outcome);
+        }
+        logger.log_failed(tx);
+        return PaymentOutcome::Rejected(bank.entity());
+    }
+
+    stats.send(Event::new(source, tx.id, EventKind::PaymentPrepared));
+    let mut not_applied = None;
+    for entity in [hotel, airline, bank] {
+        let outcome = entity.commit(tx.id).await;
+        logger.log_decision(entity.name(), tx.id, "commit", outcome);
+        if outcome != DecisionOutcome::Applied && not_applied.is_none() {
+            not_applied = Some(entity.entity());
+        }
+    }
+    stats.send(Event::new(source, tx.id, EventKind::PaymentCommitted));
+    match not_applied {
+        Some(entity) => PaymentOutcome::Inconsistent(entity),
+        None => PaymentOutcome::Committed,
+    }
+}
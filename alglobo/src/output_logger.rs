@@ -1,21 +1,40 @@
-use helpers::alglobo_transaction::AlgloboTransaction;
+use helpers::{alglobo_transaction::AlgloboTransaction, protocol::DecisionOutcome};
 extern crate csv;
 use csv::{Writer, WriterBuilder};
+use serde::Serialize;
 use std::{fs::File, io::Result, path::Path};
 
+/// A commit or abort that a participant did not apply.
+#[derive(Serialize)]
+struct ReconciliationRecord<'a> {
+    transaction_id: u32,
+    entity: &'a str,
+    operation: &'a str,
+    outcome: String,
+}
+
 pub struct OutputLogger {
     failed_writer: Writer<File>,
     processed_writer: Writer<File>,
+    reconciliation_writer: Writer<File>,
+    reconciliation_count: u32,
 }
 
 impl OutputLogger {
-    pub fn new(failed_path: String, processed_path: String) -> Result<Self> {
+    pub fn new(
+        failed_path: String,
+        processed_path: String,
+        reconciliation_path: String,
+    ) -> Result<Self> {
         let failed_writer = Self::create_or_append(failed_path)?;
         let processed_writer = Self::create_or_append(processed_path)?;
+        let reconciliation_writer = Self::create_or_append(reconciliation_path)?;
 
         Ok(Self {
             failed_writer,
             processed_writer,
+            reconciliation_writer,
+            reconciliation_count: 0,
         })
     }
 
@@ -53,4 +72,39 @@ impl OutputLogger {
             .flush()
             .expect("cannot write to successful transaction log");
     }
+
+    /// Records a decision that `entity` did not apply so it can be reconciled
+    /// manually later.
+    pub fn log_decision(
+        &mut self,
+        entity: &str,
+        transaction_id: u32,
+        operation: &str,
+        outcome: DecisionOutcome,
+    ) {
+        if outcome == DecisionOutcome::Applied {
+            return;
+        }
+        println!(
+            "{} of transaction {} was not applied by {}: {:?}",
+            operation, transaction_id, entity, outcome
+        );
+        self.reconciliation_count += 1;
+        self.reconciliation_writer
+            .serialize(ReconciliationRecord {
+                transaction_id,
+                entity,
+                operation,
+                outcome: format!("{:?}", outcome),
+            })
+            .expect("cannot write to reconciliation report");
+        self.reconciliation_writer
+            .flush()
+            .expect("cannot write to reconciliation report");
+    }
+
+    /// Number of decisions logged for reconciliation by this logger.
+    pub fn reconciliation_count(&self) -> u32 {
+        self.reconciliation_count
+    }
 }
@@ -1,4 +1,4 @@
-use crate::leader_election::{control_message::PeerId, leader_election_trait::LeaderElection};
+use helpers::leader_election::{control_message::PeerId, leader_election_trait::LeaderElection};
 
 pub struct Replication<L: LeaderElection> {
     leader_election_strategy: L,
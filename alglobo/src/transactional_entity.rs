@@ -1,23 +1,63 @@
 use std::io::Result;
+use std::time::Instant;
 use tokio::net::TcpStream;
 
-use helpers::{alglobo_transaction::AlgloboTransaction, protocol::Protocol};
+use helpers::{
+    alglobo_transaction::AlgloboTransaction,
+    event::{Event, EventKind, EventSource, ABORT_PHASE, COMMIT_PHASE, PREPARE_PHASE},
+    protocol::{DecisionOutcome, Protocol},
+};
 
 pub struct TransactionalEntity {
+    name: &'static str,
+    entity: u8,
+    /// The replica talking to the entity, as reported to stats.
+    source: EventSource,
     host: String,
     protocol: Protocol,
+    /// How long each phase took since the last `take_phase_durations`.
+    phase_durations: Vec<Event>,
 }
 
 impl TransactionalEntity {
-    pub async fn new(host: &str) -> Result<Self> {
+    pub async fn new(
+        name: &'static str,
+        entity: u8,
+        host: &str,
+        source: EventSource,
+    ) -> Result<Self> {
         Ok(Self {
+            name,
+            entity,
+            source,
             host: host.into(),
             protocol: Protocol::new(TcpStream::connect(host).await?),
+            phase_durations: Vec::new(),
         })
     }
 
+    fn record_phase(&mut self, transaction_id: u32, phase: u8, started: Instant) {
+        self.phase_durations.push(Event::new(
+            self.source,
+            transaction_id,
+            EventKind::PhaseDuration {
+                entity: self.entity,
+                phase,
+                duration_ms: started.elapsed().as_millis() as u32,
+            },
+        ));
+    }
+
+    /// Returns the durations recorded so far, to be reported to Stats.
+    pub fn take_phase_durations(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.phase_durations)
+    }
+
     pub async fn create_transaction(&mut self, transaction: &AlgloboTransaction) -> bool {
-        if let Ok(success) = self.protocol.prepare(transaction).await {
+        let started = Instant::now();
+        let result = self.protocol.prepare(transaction).await;
+        self.record_phase(transaction.id, PREPARE_PHASE, started);
+        if let Ok(success) = result {
             success
         } else {
             // Connection may have failed, try to reconnect to the entity
@@ -28,12 +68,36 @@ impl TransactionalEntity {
         }
     }
 
-    // We assume the only operation that may fail is `create_transaction`.
-    pub async fn commit(&mut self, transaction_id: u32) {
-        let _ = self.protocol.commit(transaction_id).await;
+    /// Id of the entity in the events sent to stats.
+    pub fn entity(&self) -> u8 {
+        self.entity
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // We assume the only operation that may fail is `create_transaction`, but
+    // the entity may still not know about the transaction we are deciding.
+    pub async fn commit(&mut self, transaction_id: u32) -> DecisionOutcome {
+        let started = Instant::now();
+        let outcome = self
+            .protocol
+            .commit(transaction_id)
+            .await
+            .unwrap_or(DecisionOutcome::Failed);
+        self.record_phase(transaction_id, COMMIT_PHASE, started);
+        outcome
     }
 
-    pub async fn abort(&mut self, transaction_id: u32) {
-        let _ = self.protocol.abort(transaction_id).await;
+    pub async fn abort(&mut self, transaction_id: u32) -> DecisionOutcome {
+        let started = Instant::now();
+        let outcome = self
+            .protocol
+            .abort(transaction_id)
+            .await
+            .unwrap_or(DecisionOutcome::Failed);
+        self.record_phase(transaction_id, ABORT_PHASE, started);
+        outcome
     }
 }
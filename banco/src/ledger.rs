@@ -0,0 +1,378 @@
+use crate::rates::Conversion;
+use csv::{Reader, Writer, WriterBuilder};
+use helpers::currency::Currency;
+use helpers::event::format_utc;
+use helpers::money::{Money, MoneyError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A balanced movement of `amount` from `debit_account` to `credit_account`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub entry_id: u64,
+    pub transaction_id: u32,
+    /// Entry undone by this one, if it is a reversal.
+    pub reverses: Option<u64>,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub debit_account: String,
+    pub credit_account: String,
+    /// Amount in minor units of the currency of the bank accounts.
+    pub amount: Money,
+    /// Amount paid by the client, in minor units of `original_currency`.
+    pub original_amount: Money,
+    pub original_currency: Currency,
+    /// Exchange rate used to convert `original_amount` into `amount`.
+    pub rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct StatementLine<'a> {
+    date: String,
+    entry_id: u64,
+    transaction_id: u32,
+    counterpart: &'a str,
+    debit: Money,
+    credit: Money,
+    balance: i64,
+    original_amount: Money,
+    original_currency: Currency,
+    rate: f64,
+}
+
+/// Double-entry journal of every transfer committed by the bank.
+///
+/// Recording is idempotent, so a replica can replay the decisions shipped by
+/// the primary over the entries it already had.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    entries: Vec<JournalEntry>,
+    /// Where every new entry is appended, if the journal is persisted.
+    journal: Option<Writer<File>>,
+}
+
+impl Ledger {
+    /// Loads the journal at `path`, creating it if it does not exist, and
+    /// appends every new entry to it.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut entries: Vec<JournalEntry> = Vec::new();
+        for entry in Reader::from_reader(&file).deserialize() {
+            let entry: JournalEntry = entry?;
+            // Replicas sharing the file append the same entries.
+            if entry.entry_id > entries.len() as u64 {
+                entries.push(entry);
+            }
+        }
+        let journal = WriterBuilder::new().has_headers(is_new).from_writer(file);
+        Ok(Self {
+            entries,
+            journal: Some(journal),
+        })
+    }
+
+    /// Records a transfer at `timestamp` (seconds since the unix epoch) and
+    /// returns whether it is new, as opposed to one that was already recorded.
+    pub fn record(
+        &mut self,
+        transaction_id: u32,
+        timestamp: u64,
+        debit: &str,
+        credit: &str,
+        conversion: &Conversion,
+    ) -> bool {
+        let recorded = self.entries.iter().any(|entry| {
+            entry.transaction_id == transaction_id
+                && entry.reverses.is_none()
+                && entry.timestamp == timestamp
+                && entry.debit_account == debit
+                && entry.credit_account == credit
+        });
+        if recorded {
+            return false;
+        }
+        self.append(transaction_id, None, timestamp, debit, credit, conversion);
+        true
+    }
+
+    fn append(
+        &mut self,
+        transaction_id: u32,
+        reverses: Option<u64>,
+        timestamp: u64,
+        debit: &str,
+        credit: &str,
+        conversion: &Conversion,
+    ) {
+        let entry = JournalEntry {
+            entry_id: self.entries.len() as u64 + 1,
+            transaction_id,
+            reverses,
+            timestamp,
+            debit_account: debit.into(),
+            credit_account: credit.into(),
+            amount: conversion.amount,
+            original_amount: conversion.original_amount,
+            original_currency: conversion.currency,
+            rate: conversion.rate,
+        };
+        if let Some(journal) = &mut self.journal {
+            // The bank cannot tell finance about a transfer it did not keep.
+            journal
+                .serialize(&entry)
+                .expect("Could not append to the ledger");
+            journal.flush().expect("Could not append to the ledger");
+        }
+        self.entries.push(entry);
+    }
+
+    /// Records the reverse of every entry of `transaction_id` that was not
+    /// reversed yet, at `timestamp` and the amounts and rates of the original
+    /// entries, and returns the new entries.
+    pub fn reverse(&mut self, transaction_id: u32, timestamp: u64) -> Vec<JournalEntry> {
+        let originals: Vec<JournalEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.transaction_id == transaction_id && entry.reverses.is_none())
+            .filter(|original| {
+                !self
+                    .entries
+                    .iter()
+                    .any(|entry| entry.reverses == Some(original.entry_id))
+            })
+            .cloned()
+            .collect();
+        let first_reversal = self.entries.len();
+        for entry in originals {
+            self.append(
+                transaction_id,
+                Some(entry.entry_id),
+                timestamp,
+                &entry.credit_account,
+                &entry.debit_account,
+                &Conversion {
+                    amount: entry.amount,
+                    original_amount: entry.original_amount,
+                    currency: entry.original_currency,
+                    rate: entry.rate,
+                },
+            );
+        }
+        self.entries[first_reversal..].to_vec()
+    }
+
+    /// Makes sure every entry reached the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        match &mut self.journal {
+            Some(journal) => {
+                journal.flush()?;
+                journal.get_ref().sync_all()
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Balance of `account` as credits minus debits, so clients end up with
+    /// negative balances and merchants with positive ones.
+    pub fn balance(&self, account: &str) -> Result<i64, MoneyError> {
+        self.balance_before(account, u64::MAX)
+    }
+
+    fn balance_before(&self, account: &str, timestamp: u64) -> Result<i64, MoneyError> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.timestamp < timestamp)
+            .try_fold(0, |balance, entry| Self::apply(balance, entry, account))
+    }
+
+    /// Adds the movement of `entry` on `account` to `balance`.
+    fn apply(balance: i64, entry: &JournalEntry, account: &str) -> Result<i64, MoneyError> {
+        let amount = entry.amount.to_signed()?;
+        let mut balance = balance;
+        if entry.credit_account == account {
+            balance = balance.checked_add(amount).ok_or(MoneyError::Overflow)?;
+        }
+        if entry.debit_account == account {
+            balance = balance.checked_sub(amount).ok_or(MoneyError::Underflow)?;
+        }
+        Ok(balance)
+    }
+
+    /// Writes one `<account>.csv` statement per account into `directory` with
+    /// the entries between the `from` and `to` dates (`YYYY-MM-DD`, both
+    /// inclusive). The balance column starts from the balance before `from`.
+    ///
+    /// Returns the number of statements written.
+    pub fn export_statements(&self, from: &str, to: &str, directory: &Path) -> io::Result<usize> {
+        let invalid_date = |date: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid date {}, expected YYYY-MM-DD", date),
+            )
+        };
+        let start = parse_date(from).ok_or_else(|| invalid_date(from))?;
+        let end = parse_date(to).ok_or_else(|| invalid_date(to))? + SECONDS_PER_DAY;
+        let invalid_balance = |e: MoneyError| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("cannot compute balance: {}", e),
+            )
+        };
+
+        let accounts: BTreeSet<&str> = self
+            .entries
+            .iter()
+            .flat_map(|entry| [entry.debit_account.as_str(), entry.credit_account.as_str()])
+            .collect();
+
+        std::fs::create_dir_all(directory)?;
+        for account in &accounts {
+            let mut writer = Writer::from_path(directory.join(format!("{}.csv", account)))?;
+            let mut balance = self
+                .balance_before(account, start)
+                .map_err(invalid_balance)?;
+            for entry in self
+                .entries
+                .iter()
+                .filter(|entry| entry.timestamp >= start && entry.timestamp < end)
+            {
+                if entry.debit_account != *account && entry.credit_account != *account {
+                    continue;
+                }
+                balance = Self::apply(balance, entry, account).map_err(invalid_balance)?;
+                let (counterpart, debit, credit) = if entry.debit_account == *account {
+                    (entry.credit_account.as_str(), entry.amount, Money::ZERO)
+                } else {
+                    (entry.debit_account.as_str(), Money::ZERO, entry.amount)
+                };
+                writer.serialize(StatementLine {
+                    date: format_date(entry.timestamp),
+                    entry_id: entry.entry_id,
+                    transaction_id: entry.transaction_id,
+                    counterpart,
+                    debit,
+                    credit,
+                    balance,
+                    original_amount: entry.original_amount,
+                    original_currency: entry.original_currency,
+                    rate: entry.rate,
+                })?;
+            }
+            writer.flush()?;
+        }
+        Ok(accounts.len())
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date into seconds since the unix epoch (UTC).
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    u64::try_from(days).ok().map(|days| days * SECONDS_PER_DAY)
+}
+
+/// Formats seconds since the unix epoch as a `YYYY-MM-DD` date (UTC).
+fn format_date(timestamp: u64) -> String {
+    let mut date = format_utc(timestamp * 1000);
+    date.truncate("YYYY-MM-DD".len());
+    date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(amount: u64) -> Conversion {
+        let amount = Money::from_minor_units(amount);
+        Conversion {
+            amount,
+            original_amount: amount,
+            currency: "USD".parse().unwrap(),
+            rate: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2022-06-15"), Some(1655251200));
+        assert_eq!(format_date(1655251200 + 3600), "2022-06-15");
+        assert_eq!(format_date(951782400), "2000-02-29");
+        assert_eq!(parse_date("2022-13-01"), None);
+    }
+
+    #[test]
+    fn test_balances_add_up_to_zero() {
+        let mut ledger = Ledger::default();
+        ledger.record(1, 10, "lucho", "hotel", &usd(10));
+        ledger.record(1, 10, "lucho", "airline", &usd(20));
+        ledger.record(2, 20, "viole", "hotel", &usd(20));
+        ledger.record(2, 20, "viole", "airline", &usd(500));
+
+        assert_eq!(ledger.balance("lucho"), Ok(-30));
+        assert_eq!(ledger.balance("viole"), Ok(-520));
+        assert_eq!(ledger.balance("hotel"), Ok(30));
+        assert_eq!(ledger.balance("airline"), Ok(520));
+        assert_eq!(ledger.balance_before("hotel", 20), Ok(10));
+
+        assert_eq!(ledger.reverse(2, 30).len(), 2);
+        assert_eq!(ledger.balance("viole"), Ok(0));
+        assert_eq!(ledger.balance("hotel"), Ok(10));
+        assert_eq!(ledger.balance("airline"), Ok(20));
+
+        ledger.record(3, 30, "lucho", "hotel", &usd(u64::MAX));
+        assert_eq!(ledger.balance("lucho"), Err(MoneyError::Overflow));
+    }
+
+    #[test]
+    fn test_journal_survives_restarts_and_replays() {
+        let path = std::env::temp_dir().join(format!("ledger-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut ledger = Ledger::open(&path).unwrap();
+        assert!(ledger.record(1, 10, "lucho", "hotel", &usd(10)));
+        assert!(ledger.record(1, 10, "lucho", "airline", &usd(20)));
+        assert_eq!(ledger.reverse(1, 20).len(), 2);
+        drop(ledger);
+
+        let mut ledger = Ledger::open(&path).unwrap();
+        // Replaying the same decisions records nothing new.
+        assert!(!ledger.record(1, 10, "lucho", "hotel", &usd(10)));
+        assert!(ledger.reverse(1, 20).is_empty());
+        assert!(ledger.record(2, 30, "viole", "hotel", &usd(5)));
+        drop(ledger);
+
+        let ledger = Ledger::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ledger.entries().len(), 5);
+        assert_eq!(ledger.entries()[2].reverses, Some(1));
+        assert_eq!(ledger.balance("hotel"), Ok(5));
+        assert_eq!(ledger.balance("lucho"), Ok(0));
+    }
+}
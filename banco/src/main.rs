@@ -1,103 +1,315 @@
-use actix::{Actor, Context, Handler};
+mod ledger;
+mod rates;
+
 use helpers::alglobo_transaction::AlgloboTransaction;
-use helpers::entity_main::run_entity;
-use helpers::TransactionMessage;
+use helpers::args::Args;
+use helpers::entity_main::{run_entity, EntityOptions, ReplicationOptions};
+use helpers::event::BANK_ENTITY;
+use helpers::money::{Money, MoneyError};
+use helpers::participant::Participant;
+use ledger::Ledger;
+use rates::{Conversion, ConversionError, ExchangeRates};
 use std::collections::HashMap;
-use tokio::net::TcpListener;
+use std::path::{Component, Path, PathBuf};
+
+/// Base port of the election between the bank replicas, apart from AlGlobo's.
+const BANK_ELECTION_PORT: u16 = 28000;
+const BANK_REPLICATION_PORT: u16 = 9995;
 
-enum TransactionState {
-    Accepted { tx: AlgloboTransaction },
-    Abort,
-    Commit,
+/// Prices of a prepared transaction converted to the currency of the accounts,
+/// so the commit transfers exactly what was held at prepare time.
+struct HeldTransfer {
+    hotel: Conversion,
+    airline: Conversion,
+}
+
+impl HeldTransfer {
+    fn total(&self) -> Result<Money, MoneyError> {
+        self.hotel.amount.checked_add(self.airline.amount)
+    }
 }
 
+/// Every account and amount is kept in minor units of `rates.base()`.
+///
+/// Transfers that would overflow an account are rejected at prepare time, so
+/// commits and aborts can always be applied.
 struct Bank {
-    hotel_account: u32,
-    airline_account: u32,
-    transaction_log: HashMap<u32, TransactionState>,
+    hotel_account: Money,
+    airline_account: Money,
+    /// Funds taken from clients for prepared transactions that have not been
+    /// decided yet.
+    held_funds: Money,
+    held_transfers: HashMap<u32, HeldTransfer>,
+    rates: ExchangeRates,
+    ledger: Ledger,
+    /// The admin `export` command only writes statements under it.
+    statements_dir: PathBuf,
 }
 
 impl Bank {
-    pub fn new() -> Self {
+    /// Starts with the merchant accounts at their balance in `ledger`.
+    pub fn new(rates: ExchangeRates, ledger: Ledger, statements_dir: PathBuf) -> Self {
+        let account = |name| {
+            ledger
+                .balance(name)
+                .ok()
+                .and_then(|balance| u64::try_from(balance).ok())
+                .map(Money::from_minor_units)
+                .unwrap_or_else(|| panic!("Invalid {} balance in the ledger", name))
+        };
         Self {
-            hotel_account: 0,
-            airline_account: 0,
-            transaction_log: HashMap::new(),
+            hotel_account: account("hotel"),
+            airline_account: account("airline"),
+            held_funds: Money::ZERO,
+            held_transfers: HashMap::new(),
+            rates,
+            ledger,
+            statements_dir,
         }
     }
-}
-impl Actor for Bank {
-    type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
-        println!("[Bank] Iniciado");
+    /// Where to export the statements asked for as `directory`, which must
+    /// be relative and stay under `statements_dir`.
+    fn statements_path(&self, directory: &str) -> Result<PathBuf, String> {
+        let directory = Path::new(directory);
+        let stays_inside = directory
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !stays_inside {
+            return Err(format!(
+                "{} must be a relative path without ..",
+                directory.display()
+            ));
+        }
+        Ok(self.statements_dir.join(directory))
+    }
+
+    fn export(&self, from: &str, to: &str, directory: &str) -> Result<String, String> {
+        let path = self.statements_path(directory)?;
+        let count = self
+            .ledger
+            .export_statements(from, to, &path)
+            .map_err(|e| e.to_string())?;
+        Ok(format!(
+            "{} statements written to {}",
+            count,
+            path.display()
+        ))
     }
 
-    fn stopped(&mut self, _ctx: &mut Context<Self>) {
-        println!("[Bank] Detenido");
+    fn convert(&self, transaction: &AlgloboTransaction) -> Result<HeldTransfer, ConversionError> {
+        Ok(HeldTransfer {
+            hotel: self
+                .rates
+                .convert(transaction.hotel_price, transaction.hotel_currency)?,
+            airline: self
+                .rates
+                .convert(transaction.airline_price, transaction.airline_currency)?,
+        })
+    }
+
+    /// Holds the funds of `transfer`, making sure that crediting every held
+    /// transfer to either account cannot overflow it.
+    fn hold(&mut self, transfer: &HeldTransfer) -> Result<(), MoneyError> {
+        let held_funds = self.held_funds.checked_add(transfer.total()?)?;
+        self.hotel_account.checked_add(held_funds)?;
+        self.airline_account.checked_add(held_funds)?;
+        self.held_funds = held_funds;
+        Ok(())
+    }
+
+    fn release(&mut self, transfer: &HeldTransfer) {
+        self.held_funds = transfer
+            .total()
+            .and_then(|total| self.held_funds.checked_sub(total))
+            .expect("Released more funds than were held");
     }
 }
 
-impl Handler<TransactionMessage> for Bank {
-    type Result = Result<Option<bool>, std::io::Error>;
-
-    fn handle(&mut self, msg: TransactionMessage, _ctx: &mut Context<Self>) -> Self::Result {
-        println!("[Bank] handle: {:?}", msg);
-        match msg {
-            TransactionMessage::Prepare { transaction } => {
-                if self.transaction_log.contains_key(&transaction.id) {
-                    // Transaction is already in the log, so it was already prepared.
-                    return Ok(Some(true));
-                }
-
-                if transaction.client == "falla_banco" {
-                    return Ok(Some(false));
-                }
-                self.transaction_log.insert(
-                    transaction.id,
-                    TransactionState::Accepted { tx: transaction },
-                );
-
-                return Ok(Some(true));
-            }
-            TransactionMessage::Abort { transaction_id } => {
-                self.transaction_log
-                    .insert(transaction_id, TransactionState::Abort);
-            }
-            TransactionMessage::Commit { transaction_id } => {
-                match self.transaction_log.get(&transaction_id) {
-                    Some(TransactionState::Accepted { tx: transaction }) => {
-                        self.hotel_account += transaction.hotel_price;
-                        self.airline_account += transaction.airline_price;
-
-                        println!(
-                            "Sumando {} en la cuenta del hotel de cantidad {} de {}",
-                            transaction.hotel_price, self.hotel_account, transaction.client
-                        );
-                        println!(
-                            "Sumando {} en la cuenta de la aerolínea de cantidad {} de {}",
-                            transaction.airline_price, self.airline_account, transaction.client
-                        );
-
-                        self.transaction_log
-                            .insert(transaction_id, TransactionState::Commit);
-                    }
-                    Some(TransactionState::Commit) => {}
-                    Some(TransactionState::Abort) => {}
-                    None => {}
-                }
+impl Participant for Bank {
+    const NAME: &'static str = "Bank";
+    const ENTITY: u8 = BANK_ENTITY;
+
+    fn on_prepare(&mut self, transaction: &AlgloboTransaction) -> bool {
+        if transaction.client == "falla_banco" {
+            return false;
+        }
+        let transfer = match self.convert(transaction) {
+            Ok(transfer) => transfer,
+            Err(e) => {
+                println!("Rechazando la transacción {}: {}", transaction.id, e);
+                return false;
             }
-            _ => panic!("Invalid"),
+        };
+        if let Err(e) = self.hold(&transfer) {
+            println!("Rechazando la transacción {}: {}", transaction.id, e);
+            return false;
         }
+        self.held_transfers.insert(transaction.id, transfer);
+        true
+    }
 
-        Ok(Some(true))
+    fn on_commit(&mut self, transaction: &AlgloboTransaction, timestamp_ms: u64) {
+        let transfer = self
+            .held_transfers
+            .remove(&transaction.id)
+            .expect("Committed a transaction without held funds");
+        self.release(&transfer);
+        // Only new entries move the accounts, which started with the ones
+        // loaded with the ledger. Both additions were checked when the funds
+        // were held.
+        let timestamp = timestamp_ms / 1000;
+        if self.ledger.record(
+            transaction.id,
+            timestamp,
+            &transaction.client,
+            "hotel",
+            &transfer.hotel,
+        ) {
+            self.hotel_account = self
+                .hotel_account
+                .checked_add(transfer.hotel.amount)
+                .expect("Hotel account overflow");
+        }
+        if self.ledger.record(
+            transaction.id,
+            timestamp,
+            &transaction.client,
+            "airline",
+            &transfer.airline,
+        ) {
+            self.airline_account = self
+                .airline_account
+                .checked_add(transfer.airline.amount)
+                .expect("Airline account overflow");
+        }
+
+        let base = self.rates.base();
+        println!(
+            "Sumando {} en la cuenta del hotel de cantidad {} de {}",
+            base.format(transfer.hotel.amount),
+            base.format(self.hotel_account),
+            transaction.client
+        );
+        println!(
+            "Sumando {} en la cuenta de la aerolínea de cantidad {} de {}",
+            base.format(transfer.airline.amount),
+            base.format(self.airline_account),
+            transaction.client
+        );
+    }
+
+    fn on_abort(&mut self, transaction: &AlgloboTransaction) {
+        let transfer = self
+            .held_transfers
+            .remove(&transaction.id)
+            .expect("Aborted a transaction without held funds");
+        self.release(&transfer);
+        println!(
+            "Devolviendo {} + {} retenidos a {}",
+            self.rates.base().format(transfer.hotel.amount),
+            self.rates.base().format(transfer.airline.amount),
+            transaction.client
+        );
+    }
+
+    fn on_cancel(&mut self, transaction: &AlgloboTransaction, timestamp_ms: u64) {
+        for entry in self.ledger.reverse(transaction.id, timestamp_ms / 1000) {
+            // A committed transfer is still part of the merchant's account.
+            let account = match entry.debit_account.as_str() {
+                "hotel" => &mut self.hotel_account,
+                "airline" => &mut self.airline_account,
+                _ => continue,
+            };
+            *account = account
+                .checked_sub(entry.amount)
+                .expect("Refunded more than was transferred");
+        }
+        println!(
+            "Reintegrando a {} lo pagado en la transacción {}",
+            transaction.client, transaction.id
+        );
+    }
+
+    fn inventory(&self) -> String {
+        let base = self.rates.base();
+        format!(
+            "hotel_account={} airline_account={} held_funds={}",
+            base.format(self.hotel_account),
+            base.format(self.airline_account),
+            base.format(self.held_funds)
+        )
+    }
+
+    fn admin(&mut self, words: &[&str]) -> Option<String> {
+        match words {
+            ["ledger"] => Some(
+                self.ledger
+                    .entries()
+                    .iter()
+                    .map(|entry| format!("{:?}", entry))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            ["balance", account] => Some(match self.ledger.balance(account) {
+                Ok(balance) => format!("{}", balance),
+                Err(e) => format!("error: {}", e),
+            }),
+            ["export", from, to, directory] => Some(match self.export(from, to, directory) {
+                Ok(message) => message,
+                Err(e) => format!("error: {}", e),
+            }),
+            _ => None,
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.ledger.sync() {
+            println!("No se pudo guardar el libro diario: {}", e);
+        }
     }
 }
 
 #[actix_rt::main]
 async fn main() {
-    let listener = TcpListener::bind("0.0.0.0:9997")
-        .await
-        .expect("Could not open port 9997");
-    run_entity(listener, Bank::new()).await;
+    let args = Args::from_env();
+    let mut options = EntityOptions::from_args(&args, 9897);
+    let base = args
+        .get("base-currency")
+        .unwrap_or_else(|| "USD".parse().unwrap());
+    let rates_path = args
+        .get::<String>("rates")
+        .unwrap_or_else(|| "./rates.csv".into());
+    let rates = ExchangeRates::load(&rates_path, base).unwrap_or_else(|e| {
+        println!(
+            "No se pudieron cargar las cotizaciones de {}: {}, solo se aceptan pagos en {}",
+            rates_path, e, base
+        );
+        ExchangeRates::new(base)
+    });
+    if !args.has_flag("standalone") {
+        // Run as a primary/standby pair: start a second `banco` to get a
+        // standby that takes over if the primary fails.
+        options.replication = Some(ReplicationOptions {
+            election_port: BANK_ELECTION_PORT,
+            replication_port: BANK_REPLICATION_PORT,
+            primary_host: args
+                .get("primary-host")
+                .unwrap_or_else(|| format!("127.0.0.1:{}", BANK_REPLICATION_PORT)),
+        });
+    }
+    let ledger_path = args
+        .get::<String>("ledger")
+        .unwrap_or_else(|| "./ledger.csv".into());
+    let ledger = Ledger::open(Path::new(&ledger_path))
+        .unwrap_or_else(|e| panic!("No se pudo abrir el libro diario {}: {}", ledger_path, e));
+    let statements_dir = args
+        .get::<String>("statements-dir")
+        .unwrap_or_else(|| "./statements".into());
+    run_entity(
+        9997,
+        Bank::new(rates, ledger, statements_dir.into()),
+        options,
+    )
+    .await;
 }
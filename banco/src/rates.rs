@@ -0,0 +1,160 @@
+use csv::Reader;
+use helpers::currency::Currency;
+use helpers::money::{Money, MoneyError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Deserialize)]
+struct RateRecord {
+    currency: Currency,
+    rate: f64,
+}
+
+/// An amount converted to the currency of the bank accounts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversion {
+    /// Converted amount, in minor units of the base currency.
+    pub amount: Money,
+    /// Amount before the conversion, in minor units of `currency`.
+    pub original_amount: Money,
+    pub currency: Currency,
+    /// Units of the base currency paid for each unit of `currency`.
+    pub rate: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConversionError {
+    UnknownCurrency(Currency),
+    Money(MoneyError),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::UnknownCurrency(currency) => {
+                write!(f, "there is no exchange rate for {}", currency)
+            }
+            ConversionError::Money(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Exchange rates from every known currency to the currency of the bank
+/// accounts.
+#[derive(Debug)]
+pub struct ExchangeRates {
+    base: Currency,
+    rates: HashMap<Currency, f64>,
+}
+
+impl ExchangeRates {
+    /// Rates table that only knows the base currency.
+    pub fn new(base: Currency) -> Self {
+        Self {
+            base,
+            rates: HashMap::from([(base, 1.0)]),
+        }
+    }
+
+    /// Loads the rates from a csv file with `currency,rate` columns, where the
+    /// rate is how many units of `base` a unit of the currency is worth.
+    /// Rates that are not finite positive numbers are refused.
+    pub fn load(path: &str, base: Currency) -> io::Result<Self> {
+        let mut rates = Self::new(base);
+        let mut reader = Reader::from_path(path)?;
+        for result in reader.deserialize() {
+            let record: RateRecord = result?;
+            if !record.rate.is_finite() || record.rate <= 0.0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid rate {} for {}", record.rate, record.currency),
+                ));
+            }
+            rates.rates.insert(record.currency, record.rate);
+        }
+        Ok(rates)
+    }
+
+    pub fn base(&self) -> Currency {
+        self.base
+    }
+
+    /// Converts `amount` minor units of `currency` to the base currency,
+    /// rounding to the nearest minor unit.
+    pub fn convert(
+        &self,
+        amount: Money,
+        currency: Currency,
+    ) -> Result<Conversion, ConversionError> {
+        let rate = *self
+            .rates
+            .get(&currency)
+            .ok_or(ConversionError::UnknownCurrency(currency))?;
+        let converted =
+            (amount.minor_units() as f64 * rate * self.base.minor_units_per_unit() as f64
+                / currency.minor_units_per_unit() as f64)
+                .round();
+        // `u64::MAX as f64` rounds up to 2^64, which does not fit.
+        if !(0.0..u64::MAX as f64).contains(&converted) {
+            return Err(ConversionError::Money(MoneyError::Overflow));
+        }
+        Ok(Conversion {
+            amount: Money::from_minor_units(converted as u64),
+            original_amount: amount,
+            currency,
+            rate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let usd: Currency = "USD".parse().unwrap();
+        let money = Money::from_minor_units;
+        let mut rates = ExchangeRates::new(usd);
+        rates.rates.insert("JPY".parse().unwrap(), 0.0067);
+        rates.rates.insert("EUR".parse().unwrap(), 1.08);
+
+        assert_eq!(rates.convert(money(1000), usd).unwrap().amount, money(1000));
+        assert_eq!(
+            rates
+                .convert(money(1000), "EUR".parse().unwrap())
+                .unwrap()
+                .amount,
+            money(1080)
+        );
+        // 5000 yens are 33.50 dollars.
+        assert_eq!(
+            rates
+                .convert(money(5000), "JPY".parse().unwrap())
+                .unwrap()
+                .amount,
+            money(3350)
+        );
+        assert!(rates.convert(money(1000), "GBP".parse().unwrap()).is_err());
+        assert_eq!(
+            rates.convert(money(u64::MAX), "EUR".parse().unwrap()),
+            Err(ConversionError::Money(MoneyError::Overflow))
+        );
+    }
+
+    #[test]
+    fn test_invalid_rates_are_refused() {
+        let usd: Currency = "USD".parse().unwrap();
+        let path = std::env::temp_dir().join(format!("rates-{}.csv", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        for rate in ["0", "-1.08", "NaN", "inf"] {
+            std::fs::write(&path, format!("currency,rate\nEUR,{}\n", rate)).unwrap();
+            assert!(ExchangeRates::load(path_str, usd).is_err(), "{}", rate);
+        }
+        std::fs::write(&path, "currency,rate\nEUR,1.08\n").unwrap();
+        assert!(ExchangeRates::load(path_str, usd).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
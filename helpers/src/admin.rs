@@ -0,0 +1,56 @@
+use actix::{Message, Recipient};
+use std::io::Result;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const ADMIN_HELP: &str = "\
+list [<state>]                     transactions in the log, optionally by state:
+                                   prepared, committed, aborted, cancel-prepared
+                                   or cancelled
+show <id>                          a single transaction
+inventory                          balances or inventory of the entity
+counters                           transaction counters
+audit                              decisions refused by the state machine
+help                               this message";
+
+/// A command received on the admin port, already split into words.
+#[derive(Debug, Message)]
+#[rtype(result = "String")]
+pub struct AdminCommand {
+    pub words: Vec<String>,
+}
+
+/// Serves admin commands on `listener`.
+///
+/// The protocol is plain text so it can also be used with `nc`: the client
+/// sends a single line with the command and the server answers with the
+/// result and closes the connection.
+pub async fn serve_admin(listener: TcpListener, entity: Recipient<AdminCommand>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let entity = entity.clone();
+        actix_rt::spawn(async move {
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.is_err() {
+                return;
+            }
+            let words = line.split_whitespace().map(String::from).collect();
+            let response = match entity.send(AdminCommand { words }).await {
+                Ok(response) => response,
+                Err(e) => format!("error: {}", e),
+            };
+            let _ = stream.get_mut().write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Sends `command` to the admin port at `host` and returns the response.
+pub async fn query_admin(host: &str, command: &[String]) -> Result<String> {
+    let mut stream = TcpStream::connect(host).await?;
+    stream
+        .write_all(format!("{}\n", command.join(" ")).as_bytes())
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response)
+}
@@ -1,9 +1,28 @@
+use crate::currency::Currency;
+use crate::money::Money;
 use serde::Deserialize;
 use serde::Serialize;
-#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Serialize)]
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Serialize, Hash)]
 pub struct AlgloboTransaction {
     pub id: u32,
     pub client: String,
-    pub hotel_price: u32,
-    pub airline_price: u32,
+    /// Price of the hotel in minor units of `hotel_currency`.
+    pub hotel_price: Money,
+    pub hotel_currency: Currency,
+    /// Price of the flight in minor units of `airline_currency`.
+    pub airline_price: Money,
+    pub airline_currency: Currency,
+}
+
+impl AlgloboTransaction {
+    /// Hash of the whole payload, used to tell a retried prepare apart from a
+    /// different transaction reusing the same id.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
 }
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Minimal `--key value` command line parser shared by every binary.
+///
+/// A `--key` that is not followed by a value (or is followed by another
+/// `--key`) is stored as a flag. Positional arguments are kept in order.
+#[derive(Debug, Default, Clone)]
+pub struct Args {
+    options: HashMap<String, Option<String>>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    pub fn from_env() -> Self {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Self {
+        let mut result = Self::default();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            if let Some(key) = arg.strip_prefix("--") {
+                let value = match args.peek() {
+                    Some(next) if !next.starts_with("--") => args.next(),
+                    _ => None,
+                };
+                result.options.insert(key.into(), value);
+            } else {
+                result.positional.push(arg);
+            }
+        }
+        result
+    }
+
+    /// Returns the value of `--key` parsed as `T`.
+    ///
+    /// Panics if the value is present but cannot be parsed, as a typo in a
+    /// command line option should not be silently ignored.
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        let value = self.options.get(key)?.as_ref()?;
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(_) => panic!("Invalid value for --{}: {}", key, value),
+        }
+    }
+
+    pub fn has_flag(&self, key: &str) -> bool {
+        self.options.contains_key(key)
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+}
@@ -0,0 +1,59 @@
+//! Cancels a committed booking: the hotel releases the room, the airline
+//! releases the seat and the bank refunds the client.
+//!
+//! Usage: `cancel_booking <transaction_id> [--hotel-host host:port]
+//! [--airline-host host:port] [--bank-host host:port]`
+
+use helpers::args::Args;
+use helpers::cancellation::{cancel_transaction, CancellationOutcome, CancellationParticipant};
+
+#[actix_rt::main]
+async fn main() {
+    let args = Args::from_env();
+    let transaction_id: u32 = match args.positional().first().map(|id| id.parse()) {
+        Some(Ok(id)) => id,
+        _ => {
+            eprintln!(
+                "Usage: cancel_booking <transaction_id> [--hotel-host host:port] \
+                 [--airline-host host:port] [--bank-host host:port]"
+            );
+            std::process::exit(2);
+        }
+    };
+
+    let hosts = [
+        ("hotel", "hotel-host", "127.0.0.1:9999"),
+        ("airline", "airline-host", "127.0.0.1:9998"),
+        ("bank", "bank-host", "127.0.0.1:9997"),
+    ];
+    let mut participants = Vec::new();
+    for (name, option, default_host) in hosts {
+        let host: String = args.get(option).unwrap_or_else(|| default_host.into());
+        match CancellationParticipant::connect(name, &host).await {
+            Ok(participant) => participants.push(participant),
+            Err(e) => {
+                eprintln!("Could not connect to the {} at {}: {}", name, host, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    match cancel_transaction(transaction_id, &mut participants).await {
+        CancellationOutcome::Cancelled => println!("Transaction {} cancelled", transaction_id),
+        CancellationOutcome::Refused { entity } => {
+            println!(
+                "Transaction {} was not cancelled, the {} refused it",
+                transaction_id, entity
+            );
+            std::process::exit(1);
+        }
+        CancellationOutcome::Inconsistent { entities } => {
+            println!(
+                "Cancellation of transaction {} was not applied by {}, reconcile by hand",
+                transaction_id,
+                entities.join(", ")
+            );
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! Queries the admin endpoint of the bank, the airline or the hotel.
+//!
+//! Usage: `entity_admin <bank|airline|hotel|host:port> <command...>`
+
+use helpers::admin::{query_admin, ADMIN_HELP};
+
+fn admin_host(entity: &str) -> String {
+    match entity {
+        "hotel" => "127.0.0.1:9899".into(),
+        "airline" => "127.0.0.1:9898".into(),
+        "bank" => "127.0.0.1:9897".into(),
+        host => host.into(),
+    }
+}
+
+#[actix_rt::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("Usage: entity_admin <bank|airline|hotel|host:port> <command...>");
+        eprintln!("{}", ADMIN_HELP);
+        std::process::exit(2);
+    }
+
+    let host = admin_host(&args[0]);
+    match query_admin(&host, &args[1..]).await {
+        Ok(response) => print!("{}", response),
+        Err(e) => {
+            eprintln!("Could not query {}: {}", host, e);
+            std::process::exit(1);
+        }
+    }
+}
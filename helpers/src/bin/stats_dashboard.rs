@@ -0,0 +1,135 @@
+//! Live dashboard of the stats service: throughput, latency percentiles,
+//! failures per entity, the AlGlobo leader and the most recent failures.
+//! It redraws the terminal with what stats answers to queries.
+//!
+//! Usage: `stats_dashboard [--stats-host host:port] [--refresh-s seconds]`
+
+use helpers::args::Args;
+use helpers::event::format_utc;
+use helpers::event_protocol::EventProtocol;
+use helpers::stats_query::{format_ms, Percentiles, StatsReport, DEFAULT_STATS_HOST};
+use std::fmt::Write;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+const DEFAULT_REFRESH_S: u64 = 2;
+
+/// Moves the cursor home and clears the screen.
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+fn latency_columns(latency: &Percentiles) -> String {
+    format!(
+        "{:>7} {:>7} {:>7} {:>7}",
+        format_ms(latency.p50),
+        format_ms(latency.p95),
+        format_ms(latency.p99),
+        format_ms(latency.max)
+    )
+}
+
+fn render(host: &str, report: &StatsReport) -> String {
+    let mut screen = String::new();
+    let leader = match report.leader {
+        Some(leader) => format!("replica {} ({} changes)", leader, report.leader_changes),
+        None => "unknown".into(),
+    };
+    let _ = writeln!(screen, "AlGlobo stats at {}    leader : {}", host, leader);
+    let _ = writeln!(screen);
+
+    let payments = &report.payments;
+    let _ = writeln!(
+        screen,
+        "Payments, {} succeeded, {} failed, {} in flight, average {} ms",
+        payments.succeeded,
+        payments.failed,
+        payments.in_flight,
+        format_ms(payments.average_ms)
+    );
+    let _ = writeln!(
+        screen,
+        "{:<10} {:>10} {:>8} {:>7} {:>7} {:>7} {:>7}",
+        "window", "payments/s", "failed", "p50", "p95", "p99", "max"
+    );
+    for window in &report.windows {
+        let _ = writeln!(
+            screen,
+            "{:<10} {:>10.2} {:>7.1}% {}",
+            window.window,
+            window.payments_per_second,
+            100.0 * window.failure_rate,
+            latency_columns(&window.latency)
+        );
+    }
+    let _ = writeln!(
+        screen,
+        "{:<10} {:>10} {:>8} {}",
+        "lifetime",
+        "",
+        "",
+        latency_columns(&payments.latency)
+    );
+    let _ = writeln!(screen);
+
+    let _ = writeln!(screen, "Entities, time prepared until commit in ms");
+    let _ = writeln!(
+        screen,
+        "{:<10} {:>10} {:>8} {:>8} {:>7} {:>7} {:>7} {:>7}",
+        "entity", "committed", "failed", "rejected", "p50", "p95", "p99", "max"
+    );
+    for entity in &report.entities {
+        let _ = writeln!(
+            screen,
+            "{:<10} {:>10} {:>8} {:>8} {}",
+            entity.entity,
+            entity.committed,
+            entity.failed,
+            entity.payments_rejected,
+            latency_columns(&entity.prepared)
+        );
+    }
+    let _ = writeln!(screen);
+
+    let _ = writeln!(screen, "Recent failures");
+    if report.recent_failures.is_empty() {
+        let _ = writeln!(screen, "  none");
+    }
+    for failure in &report.recent_failures {
+        let _ = writeln!(
+            screen,
+            "  {}  transaction {:<6} {:<12} {}",
+            format_utc(failure.at_ms),
+            failure.transaction_id,
+            failure.source,
+            failure.reason
+        );
+    }
+    screen
+}
+
+#[actix_rt::main]
+async fn main() {
+    let args = Args::from_env();
+    let host: String = args
+        .get("stats-host")
+        .unwrap_or_else(|| DEFAULT_STATS_HOST.into());
+    let refresh = Duration::from_secs(args.get("refresh-s").unwrap_or(DEFAULT_REFRESH_S));
+
+    let mut connection: Option<EventProtocol> = None;
+    loop {
+        if connection.is_none() {
+            connection = TcpStream::connect(&host).await.ok().map(EventProtocol::new);
+        }
+        let screen = match &mut connection {
+            Some(protocol) => match protocol.query().await {
+                Ok(report) => render(&host, &report),
+                Err(e) => {
+                    connection = None;
+                    format!("Could not query stats at {}: {}, retrying\n", host, e)
+                }
+            },
+            None => format!("Could not connect to stats at {}, retrying\n", host),
+        };
+        print!("{}{}", CLEAR, screen);
+        actix_rt::time::sleep(refresh).await;
+    }
+}
@@ -0,0 +1,38 @@
+//! Prints the current aggregates of stats: payment counts, averages and
+//! percentiles, the rolling windows and the same per entity.
+//!
+//! Usage: `stats_query [--stats-host host:port] [--json]`
+
+use helpers::args::Args;
+use helpers::event_protocol::EventProtocol;
+use helpers::stats_query::DEFAULT_STATS_HOST;
+use tokio::net::TcpStream;
+
+#[actix_rt::main]
+async fn main() {
+    let args = Args::from_env();
+    let host: String = args
+        .get("stats-host")
+        .unwrap_or_else(|| DEFAULT_STATS_HOST.into());
+
+    let stream = match TcpStream::connect(&host).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Could not connect to stats at {}: {}", host, e);
+            std::process::exit(1);
+        }
+    };
+    let report = match EventProtocol::new(stream).query().await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Could not query stats at {}: {}", host, e);
+            std::process::exit(1);
+        }
+    };
+
+    if args.has_flag("json") {
+        println!("{}", String::from_utf8_lossy(&report.to_bytes()));
+    } else {
+        print!("{}", report);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::protocol::{DecisionOutcome, Protocol};
+use std::io::Result;
+use tokio::net::TcpStream;
+
+/// An entity taking part in the cancellation of a committed transaction.
+pub struct CancellationParticipant {
+    pub name: &'static str,
+    protocol: Protocol,
+}
+
+impl CancellationParticipant {
+    pub async fn connect(name: &'static str, host: &str) -> Result<Self> {
+        Ok(Self {
+            name,
+            protocol: Protocol::new(TcpStream::connect(host).await?),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CancellationOutcome {
+    /// Every participant undid the transaction.
+    Cancelled,
+    /// `entity` refused the cancellation, so the transaction stays committed.
+    Refused { entity: &'static str },
+    /// These participants did not apply the decision and must be reconciled
+    /// by hand.
+    Inconsistent { entities: Vec<&'static str> },
+}
+
+/// Cancels the committed transaction `transaction_id` with its own two phase
+/// commit round: the cancellation is prepared on every participant in order
+/// and only committed if all of them accept it.
+pub async fn cancel_transaction(
+    transaction_id: u32,
+    participants: &mut [CancellationParticipant],
+) -> CancellationOutcome {
+    for prepared in 0..participants.len() {
+        let participant = &mut participants[prepared];
+        let accepted = participant
+            .protocol
+            .prepare_cancellation(transaction_id)
+            .await
+            .unwrap_or(false);
+        if !accepted {
+            println!(
+                "{} refused to cancel transaction {}",
+                participant.name, transaction_id
+            );
+            let entity = participant.name;
+            let mut inconsistent = Vec::new();
+            for participant in participants[..prepared].iter_mut() {
+                let outcome = participant
+                    .protocol
+                    .abort_cancellation(transaction_id)
+                    .await
+                    .unwrap_or(DecisionOutcome::Failed);
+                if outcome != DecisionOutcome::Applied {
+                    inconsistent.push(participant.name);
+                }
+            }
+            if !inconsistent.is_empty() {
+                return CancellationOutcome::Inconsistent {
+                    entities: inconsistent,
+                };
+            }
+            return CancellationOutcome::Refused { entity };
+        }
+    }
+
+    let mut inconsistent = Vec::new();
+    for participant in participants.iter_mut() {
+        let outcome = participant
+            .protocol
+            .commit_cancellation(transaction_id)
+            .await
+            .unwrap_or(DecisionOutcome::Failed);
+        if outcome != DecisionOutcome::Applied {
+            println!(
+                "{} did not apply the cancellation of transaction {}: {:?}",
+                participant.name, transaction_id, outcome
+            );
+            inconsistent.push(participant.name);
+        }
+    }
+    if inconsistent.is_empty() {
+        CancellationOutcome::Cancelled
+    } else {
+        CancellationOutcome::Inconsistent {
+            entities: inconsistent,
+        }
+    }
+}
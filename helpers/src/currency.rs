@@ -0,0 +1,117 @@
+use crate::money::Money;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// ISO 4217 currency code, such as `USD` or `EUR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    /// Builds a currency from its three letter code, which must be uppercase.
+    pub fn from_code(code: [u8; 3]) -> Option<Self> {
+        if code.iter().all(u8::is_ascii_uppercase) {
+            Some(Self(code))
+        } else {
+            None
+        }
+    }
+
+    pub fn code(&self) -> [u8; 3] {
+        self.0
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only uppercase ascii letters get in, so this is always valid utf-8.
+        std::str::from_utf8(&self.0).expect("Invalid currency code")
+    }
+
+    /// Number of decimal digits of the minor unit, e.g. 2 for cents of a
+    /// dollar and 0 for yens.
+    pub fn minor_unit_digits(&self) -> u32 {
+        match self.as_str() {
+            "CLP" | "ISK" | "JPY" | "KRW" | "PYG" | "UYI" | "VND" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            _ => 2,
+        }
+    }
+
+    /// How many minor units make a major unit of this currency.
+    pub fn minor_units_per_unit(&self) -> u64 {
+        10u64.pow(self.minor_unit_digits())
+    }
+
+    /// Formats an amount in minor units as major units, e.g. `1050` USD as
+    /// `10.50 USD`.
+    pub fn format(&self, amount: Money) -> String {
+        let minor_units = amount.minor_units();
+        let digits = self.minor_unit_digits() as usize;
+        let per_unit = self.minor_units_per_unit();
+        if digits == 0 {
+            format!("{} {}", minor_units, self)
+        } else {
+            format!(
+                "{}.{:0digits$} {}",
+                minor_units / per_unit,
+                minor_units % per_unit,
+                self,
+                digits = digits
+            )
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        code.as_bytes()
+            .try_into()
+            .ok()
+            .and_then(Self::from_code)
+            .ok_or_else(|| format!("invalid currency code {:?}", code))
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = String;
+
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        code.parse()
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.as_str().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_currency() {
+        let usd: Currency = "USD".parse().unwrap();
+        let amount = Money::from_minor_units(1050);
+        assert_eq!(usd.format(amount), "10.50 USD");
+        assert_eq!(
+            "JPY".parse::<Currency>().unwrap().format(amount),
+            "1050 JPY"
+        );
+        assert_eq!(
+            "KWD".parse::<Currency>().unwrap().format(amount),
+            "1.050 KWD"
+        );
+        assert!("usd".parse::<Currency>().is_err());
+        assert!("DOLLAR".parse::<Currency>().is_err());
+    }
+}
@@ -1,31 +1,183 @@
+use crate::admin::serve_admin;
+use crate::args::Args;
+use crate::fault_injection::{Fault, FaultInjector, FaultProfile};
+use crate::leader_election::bully::BullyLeaderElection;
+use crate::leader_election::leader_election_trait::LeaderElection;
+use crate::log_shipping::{replicate_from, serve_replication, Promote};
+use crate::participant::{Participant, ParticipantActor, Shutdown};
 use crate::protocol::Protocol;
-use crate::TransactionMessage;
-use actix::{Actor, Handler};
-use std::sync::Arc;
+use crate::shutdown::wait_for_shutdown_signal;
+use crate::stats_client::{StatsClient, STATS_BUFFER_CAPACITY, STATS_FLUSH_TIMEOUT};
+use actix::{Actor, Addr};
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::net::TcpListener;
+use tokio::sync::{oneshot, watch};
 
-pub async fn run_entity<E: Actor<Context = actix::Context<E>> + Handler<TransactionMessage>>(
-    listener: TcpListener,
-    entity: E,
-) {
-    let addr = Arc::new(entity.start());
+/// How often the primary checks that it was not replaced, and retries to
+/// open the replication port while the previous primary still holds it.
+const PRIMARY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How the replicas of an entity elect their primary and ship its log.
+pub struct ReplicationOptions {
+    /// Base port of the election held among the replicas of the entity.
+    pub election_port: u16,
+    /// Port where the primary ships its log to the standby.
+    pub replication_port: u16,
+    /// Where the standby finds the log of the primary, `host:port`. The
+    /// replicas hold the election on that host too.
+    pub primary_host: String,
+}
+
+/// Options shared by every entity binary.
+pub struct EntityOptions {
+    pub faults: FaultProfile,
+    pub prepare_ttl: Option<Duration>,
+    pub admin_port: u16,
+    pub stats_host: Option<String>,
+    pub replication: Option<ReplicationOptions>,
+}
+
+impl EntityOptions {
+    /// Builds the options from the command line. See `FaultProfile::from_args`
+    /// for the fault injection ones; `--prepare-ttl-ms` sets how long a
+    /// prepared transaction waits for a decision before presuming abort and
+    /// `--admin-port` overrides the port of the admin endpoint, which only
+    /// listens on the loopback interface. With
+    /// `--stats-host` the outcome of each transaction is reported to stats,
+    /// buffering the events while it is unreachable.
+    pub fn from_args(args: &Args, default_admin_port: u16) -> Self {
+        Self {
+            faults: FaultProfile::from_args(args).unwrap_or_else(|e| {
+                eprintln!("Invalid fault injection options: {}", e);
+                std::process::exit(2);
+            }),
+            prepare_ttl: args.get("prepare-ttl-ms").map(Duration::from_millis),
+            admin_port: args.get("admin-port").unwrap_or(default_admin_port),
+            stats_host: args.get("stats-host"),
+            replication: None,
+        }
+    }
+}
+
+/// Serves AlGlobo's transaction messages for `entity` on `port` until SIGINT
+/// or SIGTERM.
+///
+/// A replicated entity first waits as a standby, applying the log shipped by
+/// its primary, and only opens `port` once it becomes the primary itself.
+///
+/// On shutdown, no more connections are accepted, every connection finishes
+/// the message it is handling and the participant is given a chance to flush
+/// its state before returning.
+pub async fn run_entity<P: Participant>(port: u16, entity: P, options: EntityOptions) {
+    let mut actor = ParticipantActor::new(entity).with_prepare_ttl(options.prepare_ttl);
+    let stats = options
+        .stats_host
+        .as_ref()
+        .map(|stats_host| StatsClient::new(stats_host, STATS_BUFFER_CAPACITY));
+    if let Some(stats) = &stats {
+        actor = actor.with_stats(stats.clone());
+    }
+    if options.replication.is_some() {
+        actor = actor.with_replication();
+    }
+    let addr = Arc::new(actor.start());
+
+    // Resolves if another replica is elected while this one is the primary.
+    let replaced = match &options.replication {
+        Some(replication) => Some(become_primary(&addr, replication).await),
+        None => None,
+    };
+    let replaced = async move {
+        match replaced {
+            Some(replaced) => {
+                let _ = replaced.await;
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(replaced);
+    let mut fenced = false;
+
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .unwrap_or_else(|_| panic!("Could not open port {}", port));
+    // The admin commands are not authenticated, so only local users get them.
+    let admin_listener = TcpListener::bind(("127.0.0.1", options.admin_port))
+        .await
+        .unwrap_or_else(|_| panic!("Could not open admin port {}", options.admin_port));
+    actix_rt::spawn(serve_admin(admin_listener, (*addr).clone().recipient()));
+    let injector = Arc::new(Mutex::new(FaultInjector::new(options.faults)));
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let shutdown_signal = wait_for_shutdown_signal();
+    tokio::pin!(shutdown_signal);
     let mut handles = Vec::new();
-    while let Ok((stream, _)) = listener.accept().await {
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(_) => break,
+            },
+            _ = &mut shutdown_signal => {
+                println!("[{}] Shutting down, waiting for in-flight messages", P::NAME);
+                break;
+            }
+            _ = &mut replaced => {
+                println!(
+                    "[{}] Another replica was elected primary, stopping this one",
+                    P::NAME
+                );
+                fenced = true;
+                break;
+            }
+        };
+        // Forget about the connections that are already closed.
+        handles.retain(|handle: &actix_rt::task::JoinHandle<()>| !handle.is_finished());
+
         let addr = addr.clone();
+        let injector = injector.clone();
+        let mut shutdown = shutdown_receiver.clone();
         let mut protocol = Protocol::new(stream);
         handles.push(actix_rt::spawn(async move {
             loop {
-                let message = protocol.receive().await;
+                let message = tokio::select! {
+                    message = protocol.receive() => message,
+                    _ = shutdown.changed() => break,
+                };
                 if let Some(message) = message {
-                    if let Ok(Some(result)) = addr.send(message).await.unwrap() {
+                    let message = match message {
+                        Ok(message) => message,
+                        Err(e) => {
+                            println!("Closing connection after an invalid message: {}", e);
+                            break;
+                        }
+                    };
+                    let (latency, fault) = injector.lock().expect("Mutex poisoned").next(&message);
+                    actix_rt::time::sleep(latency).await;
+                    match fault {
+                        Fault::None => {}
+                        Fault::Reject => {
+                            println!("[FAULT] Rejecting {:?}", message);
+                            let _ = protocol.send_failure().await;
+                            continue;
+                        }
+                        Fault::Drop => {
+                            println!("[FAULT] Dropping connection on {:?}", message);
+                            break;
+                        }
+                        Fault::Crash => {
+                            println!("[FAULT] Crashing on {:?}", message);
+                            std::process::exit(1);
+                        }
+                    }
+
+                    if let Ok(Some(reply)) = addr.send(message).await.unwrap() {
                         // We don't really care if we could send the response or not. At this point
                         // there is nothing we can do if the client does not want to hear our
                         // response.
-                        if result {
-                            let _ = protocol.send_ok().await;
-                        } else {
-                            let _ = protocol.send_failure().await;
-                        }
+                        let _ = protocol.send(reply).await;
                     }
                 } else {
                     println!("Client disconnected");
@@ -35,7 +187,103 @@ pub async fn run_entity<E: Actor<Context = actix::Context<E>> + Handler<Transact
         }))
     }
 
+    let _ = shutdown_sender.send(true);
     for handle in handles {
         handle.await.ok();
     }
+    let _ = addr.send(Shutdown).await;
+    if let Some(stats) = stats {
+        if !stats.flush(STATS_FLUSH_TIMEOUT).await {
+            println!("[{}] Some events could not be sent to stats", P::NAME);
+        }
+    }
+    if fenced {
+        // Restarted, it rejoins as a standby of the new primary.
+        std::process::exit(1);
+    }
+}
+
+/// Joins the election among the replicas of the entity and follows the
+/// primary as a standby until this replica is elected. Then it starts
+/// shipping its own log to the next standby.
+///
+/// The returned receiver resolves if another replica is elected afterwards,
+/// so this one stops acting as the primary.
+async fn become_primary<P: Participant>(
+    addr: &Addr<ParticipantActor<P>>,
+    replication: &ReplicationOptions,
+) -> oneshot::Receiver<()> {
+    // The election blocks the calling thread, and ours has to keep running the
+    // actor while it applies the log of the primary.
+    let election_port = replication.election_port;
+    let primary_host = replication.primary_host.clone();
+    let mut election = actix_rt::task::spawn_blocking(move || {
+        BullyLeaderElection::discovery_keeping_leader(peers_host(&primary_host)?, election_port)
+    })
+    .await
+    .expect("Election thread panicked")
+    .expect("Could not join the election");
+
+    let standby = actix_rt::spawn(replicate_from(
+        replication.primary_host.clone(),
+        addr.clone().recipient(),
+    ));
+    let mut reported = false;
+    let listener = loop {
+        election = actix_rt::task::spawn_blocking(move || {
+            election.wait_until_becoming_leader();
+            election
+        })
+        .await
+        .expect("Election thread panicked");
+        // Only promoted once the previous primary let go of the port, until
+        // then this replica keeps following it.
+        match TcpListener::bind(("0.0.0.0", replication.replication_port)).await {
+            Ok(listener) => break listener,
+            Err(e) => {
+                if !reported {
+                    println!(
+                        "[{}] Could not open replication port {}, waiting for the previous primary to stop: {}",
+                        P::NAME,
+                        replication.replication_port,
+                        e
+                    );
+                    reported = true;
+                }
+                actix_rt::time::sleep(PRIMARY_CHECK_INTERVAL).await;
+            }
+        }
+    };
+    standby.abort();
+
+    println!(
+        "[{}] Replica {} is the primary",
+        P::NAME,
+        election.get_current_id()
+    );
+    let _ = addr.send(Promote).await;
+    actix_rt::spawn(serve_replication(
+        listener,
+        addr.clone().recipient(),
+        addr.clone().recipient(),
+    ));
+
+    let (replaced, receiver) = oneshot::channel();
+    // Also keeps the election alive, so the other replicas keep seeing us as
+    // the primary.
+    std::thread::spawn(move || {
+        while election.is_leader() {
+            std::thread::sleep(PRIMARY_CHECK_INTERVAL);
+        }
+        let _ = replaced.send(());
+    });
+    receiver
+}
+
+/// Address of the host part of `host:port`.
+fn peers_host(host: &str) -> io::Result<IpAddr> {
+    host.to_socket_addrs()?
+        .next()
+        .map(|address| address.ip())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, host.to_string()))
 }
@@ -1,18 +1,239 @@
 use actix::Message;
+use std::fmt;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Ids used in the `entity` field of the events sent by each participant.
+pub const HOTEL_ENTITY: u8 = 1;
+pub const AIRLINE_ENTITY: u8 = 2;
+pub const BANK_ENTITY: u8 = 3;
 
+pub fn entity_name(entity: u8) -> &'static str {
+    match entity {
+        HOTEL_ENTITY => "hotel",
+        AIRLINE_ENTITY => "airline",
+        BANK_ENTITY => "bank",
+        _ => "unknown",
+    }
+}
+
+/// Phases of a transaction timed by AlGlobo for each entity, used in the
+/// `phase` field of `PhaseDuration`.
+pub const PREPARE_PHASE: u8 = 1;
+pub const COMMIT_PHASE: u8 = 2;
+pub const ABORT_PHASE: u8 = 3;
+
+pub fn phase_name(phase: u8) -> &'static str {
+    match phase {
+        PREPARE_PHASE => "prepare",
+        COMMIT_PHASE => "commit",
+        ABORT_PHASE => "abort",
+        _ => "unknown",
+    }
+}
+
+/// Why a participant failed a transaction, used in the `code` field of
+/// `TxFailure` so failures can be counted by cause.
+pub const PREPARE_REJECTED: u8 = 1;
+pub const PREPARE_CONFLICT: u8 = 2;
+pub const COORDINATOR_ABORT: u8 = 3;
+pub const PREPARE_EXPIRED: u8 = 4;
+
+pub fn failure_name(code: u8) -> &'static str {
+    match code {
+        PREPARE_REJECTED => "prepare-rejected",
+        PREPARE_CONFLICT => "prepare-conflict",
+        COORDINATOR_ABORT => "coordinator-abort",
+        PREPARE_EXPIRED => "prepare-expired",
+        _ => "unknown",
+    }
+}
+/// Who sent an event: one of the entities or an AlGlobo replica.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum EventSource {
+    Entity(u8),
+    Replica(u8),
+}
 
+impl EventSource {
+    fn to_bytes(self) -> [u8; 2] {
+        match self {
+            Self::Entity(entity) => [b'E', entity],
+            Self::Replica(replica) => [b'R', replica],
+        }
+    }
 
-#[derive(Eq, PartialEq, Debug, Message)]
+    fn from_bytes(bytes: [u8; 2]) -> io::Result<Self> {
+        match bytes[0] {
+            b'E' => Ok(Self::Entity(bytes[1])),
+            b'R' => Ok(Self::Replica(bytes[1])),
+            _ => Err(invalid("event source", &bytes)),
+        }
+    }
+}
+
+impl fmt::Display for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Entity(entity) => f.write_str(entity_name(*entity)),
+            Self::Replica(replica) => write!(f, "alglobo-{}", replica),
+        }
+    }
+}
+
+fn invalid(what: &str, bytes: &[u8]) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid {}: {:?}", what, bytes),
+    )
+}
+
+/// The `N` bytes of `bytes` at `start`, or an error if the frame is too short.
+fn array_at<const N: usize>(bytes: &[u8], start: usize) -> io::Result<[u8; N]> {
+    bytes
+        .get(start..start + N)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or_else(|| invalid("event", bytes))
+}
+
+/// Milliseconds since the unix epoch, as used in `Event::timestamp_ms`.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis() as u64)
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in UTC of a timestamp like `now_ms`.
+pub fn format_utc(timestamp_ms: u64) -> String {
+    const SECONDS_PER_DAY: u64 = 86_400;
+    let seconds = timestamp_ms / 1000;
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (seconds / SECONDS_PER_DAY) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = seconds % SECONDS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Something that happened to a transaction, as reported to stats.
+#[derive(Eq, PartialEq, Debug, Clone, Message)]
 #[rtype(result = "()")]
-pub enum Event {
-    TxSuccess { entity: u8, duration_ms: u32 },
-    TxFailure { entity: u8, reason: String },
-    PaymentSuccess { duration: u32 },
-    PaymentFailed { reason: String },
+pub struct Event {
+    /// The payment the event belongs to, 0 for events of no payment such as
+    /// `LeaderChanged`.
+    pub transaction_id: u32,
+    pub source: EventSource,
+    /// When it happened, in milliseconds since the unix epoch.
+    pub timestamp_ms: u64,
+    pub kind: EventKind,
 }
 
+/// Length of the transaction id, source and timestamp, which follow the
+/// opcode of the kind in every event.
+const HEADER_LEN: usize = 4 + 2 + 8;
+
 impl Event {
+    /// An event of `transaction_id` sent by `source` that happened now.
+    pub fn new(source: EventSource, transaction_id: u32, kind: EventKind) -> Self {
+        Self {
+            transaction_id,
+            source,
+            timestamp_ms: now_ms(),
+            kind,
+        }
+    }
+
+    /// The opcode of the kind goes first, so every frame on the stats
+    /// listener can be told apart by its first byte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let kind = self.kind.to_bytes();
+        let mut result = vec![kind[0]];
+        result.extend_from_slice(&u32::to_le_bytes(self.transaction_id));
+        result.extend_from_slice(&self.source.to_bytes());
+        result.extend_from_slice(&u64::to_le_bytes(self.timestamp_ms));
+        result.extend_from_slice(&kind[1..]);
+        result
+    }
+
+    /// Fails on a frame that is not a valid event, so a client sending one
+    /// can be disconnected.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 1 + HEADER_LEN {
+            return Err(invalid("event", bytes));
+        }
+        let mut kind = vec![bytes[0]];
+        kind.extend_from_slice(&bytes[1 + HEADER_LEN..]);
+        Ok(Self {
+            transaction_id: u32::from_le_bytes(array_at(bytes, 1)?),
+            source: EventSource::from_bytes(array_at(bytes, 5)?)?,
+            timestamp_ms: u64::from_le_bytes(array_at(bytes, 7)?),
+            kind: EventKind::from_bytes(&kind)?,
+        })
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum EventKind {
+    TxSuccess {
+        entity: u8,
+        duration_ms: u32,
+    },
+    TxFailure {
+        entity: u8,
+        code: u8,
+        reason: String,
+    },
+    PaymentSuccess {
+        duration: u32,
+    },
+    /// A payment was not committed because `entity` rejected it.
+    PaymentFailed {
+        entity: u8,
+        reason: String,
+    },
+    /// AlGlobo started the two phase commit of a payment.
+    PaymentStarted,
+    /// Every entity prepared the payment, so it is about to be committed.
+    PaymentPrepared,
+    /// AlGlobo sent the commit decision of the payment to every entity.
+    PaymentCommitted,
+    /// AlGlobo gave up on the payment, aborting it where it was prepared.
+    PaymentAborted,
+    TxExpired {
+        entity: u8,
+    },
+    /// Round trip of a single phase with an entity, as seen by AlGlobo.
+    PhaseDuration {
+        entity: u8,
+        phase: u8,
+        duration_ms: u32,
+    },
+    /// The AlGlobo replica `leader` became the leader.
+    LeaderChanged {
+        leader: u8,
+    },
+}
+
+impl EventKind {
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
             Self::TxSuccess {
@@ -23,8 +244,12 @@ impl Event {
                 result.extend_from_slice(&u32::to_le_bytes(*duration_ms));
                 result
             }
-            Self::TxFailure { entity, reason } => {
-                let mut result = vec![b'F', *entity];
+            Self::TxFailure {
+                entity,
+                code,
+                reason,
+            } => {
+                let mut result = vec![b'F', *entity, *code];
                 let reason_bytes = reason.as_bytes();
                 result.extend(reason_bytes.iter());
                 result
@@ -34,33 +259,156 @@ impl Event {
                 result.extend_from_slice(&u32::to_le_bytes(*duration));
                 result
             }
-            Self::PaymentFailed { reason } => {
-                let mut result = vec![b'X'];
+            Self::PaymentFailed { entity, reason } => {
+                let mut result = vec![b'X', *entity];
                 let reason_bytes = reason.as_bytes();
                 result.extend(reason_bytes.iter());
                 result
             }
+            Self::PaymentStarted => vec![b'B'],
+            Self::PaymentPrepared => vec![b'R'],
+            Self::PaymentCommitted => vec![b'M'],
+            Self::PaymentAborted => vec![b'T'],
+            Self::TxExpired { entity } => vec![b'E', *entity],
+            Self::PhaseDuration {
+                entity,
+                phase,
+                duration_ms,
+            } => {
+                let mut result = vec![b'L', *entity, *phase];
+                result.extend_from_slice(&u32::to_le_bytes(*duration_ms));
+                result
+            }
+            Self::LeaderChanged { leader } => vec![b'C', *leader],
         }
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        match bytes[0] {
-            b'S' => Event::TxSuccess {
-                entity: bytes[1],
-                duration_ms: u32::from_le_bytes(bytes[2..].try_into().unwrap()),
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let byte = |index: usize| {
+            bytes
+                .get(index)
+                .copied()
+                .ok_or_else(|| invalid("event", bytes))
+        };
+        // Durations are the last field, so they must end the frame.
+        let duration_at = |index: usize| {
+            if bytes.len() != index + 4 {
+                return Err(invalid("event", bytes));
+            }
+            Ok(u32::from_le_bytes(array_at(bytes, index)?))
+        };
+        let reason_at = |index: usize| {
+            bytes
+                .get(index..)
+                .map(|reason| String::from_utf8_lossy(reason).into())
+                .ok_or_else(|| invalid("event", bytes))
+        };
+        Ok(match byte(0)? {
+            b'S' => EventKind::TxSuccess {
+                entity: byte(1)?,
+                duration_ms: duration_at(2)?,
+            },
+            b'F' => EventKind::TxFailure {
+                entity: byte(1)?,
+                code: byte(2)?,
+                reason: reason_at(3)?,
+            },
+            b'P' => EventKind::PaymentSuccess {
+                duration: duration_at(1)?,
             },
+            b'X' => EventKind::PaymentFailed {
+                entity: byte(1)?,
+                reason: reason_at(2)?,
+            },
+            b'B' => EventKind::PaymentStarted,
+            b'R' => EventKind::PaymentPrepared,
+            b'M' => EventKind::PaymentCommitted,
+            b'T' => EventKind::PaymentAborted,
+            b'E' => EventKind::TxExpired { entity: byte(1)? },
+            b'L' => EventKind::PhaseDuration {
+                entity: byte(1)?,
+                phase: byte(2)?,
+                duration_ms: duration_at(3)?,
+            },
+            b'C' => EventKind::LeaderChanged { leader: byte(1)? },
+            _ => return Err(invalid("event kind", bytes)),
+        })
+    }
+}
 
-            b'F' => Event::TxFailure {
-                entity: bytes[1],
-                reason: String::from_utf8_lossy(&bytes[2..]).into(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        let kinds = [
+            EventKind::TxSuccess {
+                entity: BANK_ENTITY,
+                duration_ms: 1234,
+            },
+            EventKind::TxFailure {
+                entity: HOTEL_ENTITY,
+                code: PREPARE_REJECTED,
+                reason: "no rooms".into(),
             },
-            b'P' => Event::PaymentSuccess {
-                duration: u32::from_le_bytes(bytes[1..].try_into().unwrap()),
+            EventKind::PaymentSuccess { duration: 99 },
+            EventKind::PaymentFailed {
+                entity: BANK_ENTITY,
+                reason: "rejected by the bank".into(),
             },
-            b'X' => Event::PaymentFailed {
-                reason: String::from_utf8_lossy(&bytes[1..]).into(),
+            EventKind::PaymentStarted,
+            EventKind::PaymentPrepared,
+            EventKind::PaymentCommitted,
+            EventKind::PaymentAborted,
+            EventKind::TxExpired {
+                entity: AIRLINE_ENTITY,
             },
-            _ => panic!("Invalid Event message: {:?}", bytes),
+            EventKind::PhaseDuration {
+                entity: HOTEL_ENTITY,
+                phase: COMMIT_PHASE,
+                duration_ms: 7,
+            },
+            EventKind::LeaderChanged { leader: 30 },
+        ];
+        for kind in kinds {
+            let event = Event::new(EventSource::Replica(30), 42, kind);
+            assert_eq!(Event::from_bytes(&event.to_bytes()).unwrap(), event);
+        }
+        let event = Event::new(
+            EventSource::Entity(BANK_ENTITY),
+            7,
+            EventKind::PaymentStarted,
+        );
+        assert_eq!(Event::from_bytes(&event.to_bytes()).unwrap(), event);
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(951_827_696_789), "2000-02-29 12:34:56");
+        assert_eq!(
+            format_utc(1_655_251_200_000 + 3_599_000),
+            "2022-06-15 00:59:59"
+        );
+    }
+
+    #[test]
+    fn test_invalid_frames_are_errors() {
+        let event = Event::new(
+            EventSource::Entity(HOTEL_ENTITY),
+            7,
+            EventKind::PaymentSuccess { duration: 12 },
+        );
+        let bytes = event.to_bytes();
+        for length in 0..bytes.len() {
+            assert!(Event::from_bytes(&bytes[..length]).is_err());
         }
+        let mut unknown_source = bytes.clone();
+        unknown_source[5] = b'?';
+        assert!(Event::from_bytes(&unknown_source).is_err());
+        let mut unknown_kind = bytes;
+        unknown_kind[0] = b'?';
+        assert!(Event::from_bytes(&unknown_kind).is_err());
     }
 }
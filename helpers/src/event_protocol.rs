@@ -1,7 +1,17 @@
 use crate::event::Event;
+use crate::stats_query::{StatsReport, QUERY_OPCODE};
+use std::io;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 
+/// What a client of the stats listener sends.
+#[derive(Debug)]
+pub enum StatsRequest {
+    Event(Event),
+    /// Asks for a `StatsReport` of the current aggregates.
+    Query,
+}
+
 pub struct EventProtocol {
     stream: TcpStream,
 }
@@ -11,29 +21,55 @@ impl EventProtocol {
         Self { stream }
     }
 
-    pub async fn send_event(&mut self, event: Event) {
-        let payload = event.to_bytes();
+    async fn send_frame(&mut self, payload: Vec<u8>) -> io::Result<()> {
         let sz = payload.len() as u32;
         let mut full_payload = Vec::new();
         full_payload.extend_from_slice(&sz.to_le_bytes());
         full_payload.extend(payload);
-
-        // Stats are not mission-critical, so we can ignore this
-        // errors.
-        let _ = self.stream.write_all(&full_payload).await;
+        self.stream.write_all(&full_payload).await
     }
 
-    pub async fn recv_event(&mut self) -> Option<Event> {
+    async fn recv_frame(&mut self) -> Option<Vec<u8>> {
         let mut sz = [0u8; 4];
-        if self.stream.read_exact(&mut sz).await.is_ok() {
-            let mut buf = vec![0u8; u32::from_le_bytes(sz) as usize];
-            if self.stream.read_exact(&mut buf).await.is_err() {
-                return None;
-            }
-            let message = Event::from_bytes(&buf);
-            Some(message)
+        self.stream.read_exact(&mut sz).await.ok()?;
+        let mut buf = vec![0u8; u32::from_le_bytes(sz) as usize];
+        self.stream.read_exact(&mut buf).await.ok()?;
+        Some(buf)
+    }
+
+    pub async fn send_event(&mut self, event: Event) -> io::Result<()> {
+        self.send_frame(event.to_bytes()).await
+    }
+
+    /// `None` once the connection is closed, and an error for a frame that
+    /// is not a valid event.
+    pub async fn recv_event(&mut self) -> Option<io::Result<Event>> {
+        let buf = self.recv_frame().await?;
+        Some(Event::from_bytes(&buf))
+    }
+
+    /// Receives either an event or a query, on the stats side. `None` once
+    /// the client disconnects, and an error for a frame that is neither.
+    pub async fn recv_request(&mut self) -> Option<io::Result<StatsRequest>> {
+        let buf = self.recv_frame().await?;
+        if buf.first() == Some(&QUERY_OPCODE) {
+            Some(Ok(StatsRequest::Query))
         } else {
-            None
+            Some(Event::from_bytes(&buf).map(StatsRequest::Event))
         }
     }
+
+    /// Answers a query, on the stats side.
+    pub async fn send_report(&mut self, report: &StatsReport) -> io::Result<()> {
+        self.send_frame(report.to_bytes()).await
+    }
+
+    /// Asks stats for its current aggregates and waits for the answer.
+    pub async fn query(&mut self) -> io::Result<StatsReport> {
+        self.send_frame(vec![QUERY_OPCODE]).await?;
+        let buf = self.recv_frame().await.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "stats closed the connection")
+        })?;
+        StatsReport::from_bytes(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
 }
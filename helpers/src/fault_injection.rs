@@ -0,0 +1,179 @@
+use crate::args::Args;
+use crate::TransactionMessage;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+
+/// Describes which faults an entity should inject while serving AlGlobo.
+///
+/// Every option defaults to "no fault", so an entity started without
+/// arguments behaves as a perfectly reliable participant.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultProfile {
+    /// Probability of rejecting a `Prepare`, in `[0, 1]`.
+    pub reject_probability: f64,
+    /// Minimum latency added before handling each message.
+    pub min_latency_ms: u64,
+    /// Maximum latency added before handling each message.
+    pub max_latency_ms: u64,
+    /// Probability of closing the connection instead of handling a message.
+    pub drop_probability: f64,
+    /// Exits the process after handling this many messages.
+    pub crash_after: Option<u64>,
+    /// Seed for the random generator, so faulty runs can be reproduced.
+    pub seed: Option<u64>,
+}
+
+impl FaultProfile {
+    /// Builds a profile from the command line:
+    /// `--reject-probability`, `--min-latency-ms`, `--max-latency-ms`,
+    /// `--drop-probability`, `--crash-after` and `--seed`.
+    ///
+    /// Fails with a message for the user if the profile is not valid.
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let min_latency_ms = args.get("min-latency-ms").unwrap_or(0);
+        let profile = Self {
+            reject_probability: args.get("reject-probability").unwrap_or(0.0),
+            min_latency_ms,
+            max_latency_ms: args.get("max-latency-ms").unwrap_or(min_latency_ms),
+            drop_probability: args.get("drop-probability").unwrap_or(0.0),
+            crash_after: args.get("crash-after"),
+            seed: args.get("seed"),
+        };
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Checks that probabilities are in `[0, 1]` and that the latency range
+    /// is not empty, which `FaultInjector` relies on.
+    pub fn validate(&self) -> Result<(), String> {
+        for (option, probability) in [
+            ("reject-probability", self.reject_probability),
+            ("drop-probability", self.drop_probability),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(format!(
+                    "--{} must be between 0 and 1, got {}",
+                    option, probability
+                ));
+            }
+        }
+        if self.max_latency_ms < self.min_latency_ms {
+            return Err(format!(
+                "--max-latency-ms ({}) must not be lower than --min-latency-ms ({})",
+                self.max_latency_ms, self.min_latency_ms
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    None,
+    /// Reply with a failure without forwarding the message to the entity.
+    Reject,
+    /// Close the connection without replying.
+    Drop,
+    /// Terminate the whole process.
+    Crash,
+}
+
+pub struct FaultInjector {
+    profile: FaultProfile,
+    rng: StdRng,
+    messages_seen: u64,
+}
+
+impl FaultInjector {
+    pub fn new(profile: FaultProfile) -> Self {
+        let seed = profile.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        println!("[FAULTS] {:?} (seed {})", profile, seed);
+        Self {
+            profile,
+            rng: StdRng::seed_from_u64(seed),
+            messages_seen: 0,
+        }
+    }
+
+    /// Decides which fault, if any, applies to the next received message and
+    /// how long its handling should be delayed.
+    pub fn next(&mut self, message: &TransactionMessage) -> (Duration, Fault) {
+        self.messages_seen += 1;
+        let latency = if self.profile.max_latency_ms > self.profile.min_latency_ms {
+            self.rng
+                .gen_range(self.profile.min_latency_ms..=self.profile.max_latency_ms)
+        } else {
+            self.profile.min_latency_ms
+        };
+        let latency = Duration::from_millis(latency);
+
+        if matches!(self.profile.crash_after, Some(n) if self.messages_seen > n) {
+            return (latency, Fault::Crash);
+        }
+        if self.rng.gen_bool(self.profile.drop_probability) {
+            return (latency, Fault::Drop);
+        }
+        // Only the prepare phase may fail, commits and aborts are always honored.
+        let is_prepare = matches!(message, TransactionMessage::Prepare { .. });
+        if self.rng.gen_bool(self.profile.reject_probability) && is_prepare {
+            return (latency, Fault::Reject);
+        }
+        (latency, Fault::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepare() -> TransactionMessage {
+        TransactionMessage::Prepare {
+            transaction: crate::alglobo_transaction::AlgloboTransaction {
+                id: 1,
+                client: "test-client".into(),
+                hotel_price: crate::money::Money::from_minor_units(1),
+                hotel_currency: "USD".parse().unwrap(),
+                airline_price: crate::money::Money::from_minor_units(2),
+                airline_currency: "USD".parse().unwrap(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_default_profile_injects_nothing() {
+        let mut injector = FaultInjector::new(FaultProfile::default());
+        for _ in 0..100 {
+            assert_eq!(injector.next(&prepare()), (Duration::ZERO, Fault::None));
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_faults() {
+        let profile = FaultProfile {
+            reject_probability: 0.3,
+            min_latency_ms: 10,
+            max_latency_ms: 50,
+            drop_probability: 0.1,
+            crash_after: Some(40),
+            seed: Some(42),
+        };
+        let mut first = FaultInjector::new(profile.clone());
+        let mut second = FaultInjector::new(profile);
+        for _ in 0..50 {
+            assert_eq!(first.next(&prepare()), second.next(&prepare()));
+        }
+        assert_eq!(first.next(&prepare()).1, Fault::Crash);
+    }
+
+    #[test]
+    fn test_invalid_profiles_are_refused() {
+        let parse =
+            |line: &str| FaultProfile::from_args(&Args::parse(line.split(' ').map(String::from)));
+        assert!(parse("--reject-probability 1.5").is_err());
+        assert!(parse("--drop-probability -0.1").is_err());
+        assert!(parse("--drop-probability NaN").is_err());
+        assert!(parse("--min-latency-ms 50 --max-latency-ms 10").is_err());
+        assert!(parse("--reject-probability 1 --min-latency-ms 10").is_ok());
+    }
+}
@@ -17,7 +17,7 @@ impl<V> AtomicValue<V> {
         }
     }
 
-    pub fn load(&self) -> MutexGuard<V> {
+    pub fn load(&self) -> MutexGuard<'_, V> {
         self.value.lock().expect("Mutex poisoned")
     }
 
@@ -27,7 +27,7 @@ impl<V> AtomicValue<V> {
         self.condition.notify_all();
     }
 
-    pub fn wait_while<F: FnMut(&mut V) -> bool>(&self, predicate: F) -> MutexGuard<V> {
+    pub fn wait_while<F: FnMut(&mut V) -> bool>(&self, predicate: F) -> MutexGuard<'_, V> {
         let lock = self.value.lock().expect("Mutex poison");
         self.condition
             .wait_while(lock, predicate)
@@ -38,7 +38,7 @@ impl<V> AtomicValue<V> {
         &self,
         timeout: Duration,
         predicate: F,
-    ) -> Option<MutexGuard<V>> {
+    ) -> Option<MutexGuard<'_, V>> {
         let lock = self.value.lock().expect("Mutex poison");
         let (lock, result) = self
             .condition
@@ -3,7 +3,7 @@ extern crate rand;
 use std::{
     collections::HashSet,
     io::{ErrorKind, Result},
-    net::UdpSocket,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
     ops::Deref,
     thread::{self, JoinHandle},
     time::{Duration, SystemTime},
@@ -17,7 +17,9 @@ use super::{
     control_message::{ControlMessage, PeerId},
 };
 
-const BASE_PEER_PORT: u16 = 27000;
+/// Base port used by AlGlobo's replicas. Other replicated processes must use
+/// a different base so their elections do not mix.
+pub const BASE_PEER_PORT: u16 = 27000;
 
 const RECV_TIMEOUT: Duration = Duration::from_secs(1);
 const LEADER_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
@@ -31,6 +33,11 @@ const MAX_TEMP_PEER_ID: PeerId = 255;
 
 pub struct BullyLeaderElectionInner {
     id: PeerId,
+    /// Where the peers are reached, each on `base_port` plus its id.
+    peers_host: IpAddr,
+    base_port: u16,
+    /// Whether a leader keeps its role when a peer with a higher id joins.
+    keep_leader: bool,
     socket: UdpSocket,
     leader_id: AtomicValue<Option<PeerId>>,
     got_ok: AtomicValue<bool>,
@@ -69,18 +76,39 @@ impl State {
 
 impl BullyLeaderElection {
     pub fn discovery() -> Result<BullyLeaderElection> {
+        Self::discover(Ipv4Addr::LOCALHOST.into(), BASE_PEER_PORT, false)
+    }
+
+    /// Joins the election held among the peers listening on `base_port` of
+    /// `peers_host`. Unlike the plain bully algorithm, a peer that joins while
+    /// there is a leader follows it even if it has a higher id, so the leader
+    /// only changes once it stops answering.
+    pub fn discovery_keeping_leader(
+        peers_host: IpAddr,
+        base_port: u16,
+    ) -> Result<BullyLeaderElection> {
+        Self::discover(peers_host, base_port, true)
+    }
+
+    fn discover(
+        peers_host: IpAddr,
+        base_port: u16,
+        keep_leader: bool,
+    ) -> Result<BullyLeaderElection> {
         println!("[DISCOVERY] Trying to auto assign an id");
         let mut rng = rand::thread_rng();
         let mut retries_left = 10;
         let inner = loop {
             let random_id = rng.gen_range(MIN_TEMP_PEER_ID..=MAX_TEMP_PEER_ID);
-            if let Ok(inner) = BullyLeaderElectionInner::new(random_id) {
+            if let Ok(inner) =
+                BullyLeaderElectionInner::new(random_id, peers_host, base_port, keep_leader)
+            {
                 break inner;
             }
 
             retries_left -= 1;
             if retries_left == 0 {
-                return Err(std::io::Error::new(ErrorKind::Other, "No available IDs"));
+                return Err(std::io::Error::other("No available IDs"));
             }
         };
 
@@ -99,14 +127,16 @@ impl BullyLeaderElection {
 
         for peer_id in (MIN_PEER_ID..=MAX_PEER_ID).rev() {
             if !connected_ids.contains(&peer_id) {
-                if let Ok(new_inner) = BullyLeaderElectionInner::new(peer_id) {
+                if let Ok(new_inner) =
+                    BullyLeaderElectionInner::new(peer_id, peers_host, base_port, keep_leader)
+                {
                     println!("[DISCOVERY] Auto-assigned id: {}", peer_id);
                     return Self::new_from(new_inner);
                 }
             }
         }
 
-        Err(std::io::Error::new(ErrorKind::Other, "No available IDs"))
+        Err(std::io::Error::other("No available IDs"))
     }
 
     fn new_from(inner: BullyLeaderElectionInner) -> Result<BullyLeaderElection> {
@@ -123,20 +153,40 @@ impl BullyLeaderElection {
 }
 
 impl BullyLeaderElectionInner {
-    pub fn new(id: PeerId) -> Result<BullyLeaderElectionInner> {
-        let socket = UdpSocket::bind(Self::build_peer_address(id))?;
+    pub fn new(
+        id: PeerId,
+        peers_host: IpAddr,
+        base_port: u16,
+        keep_leader: bool,
+    ) -> Result<BullyLeaderElectionInner> {
+        // Peers on other hosts reach us on any of our addresses.
+        let local_host = if peers_host.is_loopback() {
+            peers_host
+        } else {
+            Ipv4Addr::UNSPECIFIED.into()
+        };
+        let socket = UdpSocket::bind(Self::build_peer_address(local_host, base_port, id))?;
         socket
             .set_read_timeout(Some(RECV_TIMEOUT))
             .expect("Could not set socket as non-blocking.");
 
         Ok(Self {
             id,
+            peers_host,
+            base_port,
+            keep_leader,
             socket,
             leader_id: AtomicValue::new(None),
             got_ok: AtomicValue::new(false),
             got_pong: AtomicValue::new(None),
             stop: AtomicValue::new(false),
-            current_state: AtomicValue::new(State::finding_leader_now()),
+            // Keeping the leader needs `find_new_leader` to actually ask the
+            // peers, which it skips while already finding a leader.
+            current_state: AtomicValue::new(if keep_leader {
+                State::Idle
+            } else {
+                State::finding_leader_now()
+            }),
         })
     }
 
@@ -180,7 +230,12 @@ impl BullyLeaderElectionInner {
                 }
             }
             ControlMessage::Election => {
-                if peer_from < self.id {
+                let is_leader = *self.leader_id.load() == Some(self.id);
+                if self.keep_leader && is_leader {
+                    // Tell the peer that joined who the leader is instead of
+                    // holding a new election.
+                    self.send_message(peer_from, ControlMessage::Coordinator);
+                } else if peer_from < self.id {
                     self.send_message(peer_from, ControlMessage::Ok);
                     if !matches!(current_state, State::FindingLeader { .. }) {
                         self.current_state.store(State::finding_leader_now());
@@ -222,7 +277,9 @@ impl BullyLeaderElectionInner {
         let mut buf = [0; ControlMessage::size_of()];
 
         let (size, _) = match self.socket.recv_from(&mut buf) {
-            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {
+                return Ok(None)
+            }
             r => r?,
         };
 
@@ -242,7 +299,7 @@ impl BullyLeaderElectionInner {
         self.socket
             .send_to(
                 &message.to_bytes(self.id),
-                Self::build_peer_address(dst_peer),
+                Self::build_peer_address(self.peers_host, self.base_port, dst_peer),
             )
             .unwrap();
     }
@@ -263,8 +320,8 @@ impl BullyLeaderElectionInner {
         }
     }
 
-    fn build_peer_address(peer_id: PeerId) -> String {
-        format!("127.0.0.1:{}", BASE_PEER_PORT + (peer_id as u16))
+    fn build_peer_address(host: IpAddr, base_port: u16, peer_id: PeerId) -> SocketAddr {
+        SocketAddr::new(host, base_port + (peer_id as u16))
     }
 }
 
@@ -272,6 +329,9 @@ impl Clone for BullyLeaderElectionInner {
     fn clone(&self) -> Self {
         Self {
             id: self.id,
+            peers_host: self.peers_host,
+            base_port: self.base_port,
+            keep_leader: self.keep_leader,
             socket: self.socket.try_clone().unwrap(),
             leader_id: self.leader_id.clone(),
             got_ok: self.got_ok.clone(),
@@ -315,7 +375,10 @@ impl LeaderElection for BullyLeaderElection {
 
         self.current_state.store(State::finding_leader_now());
         self.leader_id.store(None);
-        self.broadcast_message_if(ControlMessage::Election, |peer_id| self.id < peer_id);
+        // A leader with a lower id answers too when keeping the leader.
+        self.broadcast_message_if(ControlMessage::Election, |peer_id| {
+            self.keep_leader || self.id < peer_id
+        });
     }
 
     fn has_finished(&self) -> bool {
@@ -25,9 +25,3 @@ pub trait LeaderElection {
     /// Returns the Id of the current replica
     fn get_current_id(&self) -> PeerId;
 }
-
-pub trait LeaderElectionController {
-    fn on_election(&self, id: u32);
-    fn on_coordinator(&self, id: u32);
-    fn on_ok(&self, id: u32);
-}
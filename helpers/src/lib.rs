@@ -1,21 +1,65 @@
 extern crate actix;
 
+pub mod admin;
 pub mod alglobo_transaction;
+pub mod args;
+pub mod cancellation;
+pub mod currency;
 pub mod entity_main;
 pub mod event;
 pub mod event_protocol;
+pub mod fault_injection;
+pub mod leader_election;
+pub mod log_shipping;
+pub mod money;
+pub mod participant;
 pub mod protocol;
+pub mod reservations;
+pub mod shutdown;
+pub mod stats_client;
+pub mod stats_query;
 
 use actix::Message;
 use alglobo_transaction::AlgloboTransaction;
+use currency::Currency;
+use money::Money;
+use std::io;
 
-#[derive(Eq, PartialEq, Debug, Message)]
-#[rtype(result = "Result<Option<bool>, std::io::Error>")]
+#[derive(Eq, PartialEq, Debug, Clone, Message)]
+#[rtype(result = "Result<Option<TransactionMessage>, std::io::Error>")]
 pub enum TransactionMessage {
-    Prepare { transaction: AlgloboTransaction },
-    Abort { transaction_id: u32 },
-    Commit { transaction_id: u32 },
-    Response { success: bool },
+    Prepare {
+        transaction: AlgloboTransaction,
+    },
+    Abort {
+        transaction_id: u32,
+    },
+    Commit {
+        transaction_id: u32,
+    },
+    Response {
+        success: bool,
+    },
+    /// Negative response that explains why the request was refused.
+    Rejected {
+        reason: String,
+    },
+    /// Response to a commit or abort of a transaction that was never prepared.
+    UnknownTransaction {
+        transaction_id: u32,
+    },
+    /// First phase of the cancellation of a committed transaction.
+    PrepareCancellation {
+        transaction_id: u32,
+    },
+    /// Undoes a committed transaction whose cancellation was prepared.
+    CommitCancellation {
+        transaction_id: u32,
+    },
+    /// Keeps a transaction committed after preparing its cancellation.
+    AbortCancellation {
+        transaction_id: u32,
+    },
 }
 
 impl TransactionMessage {
@@ -24,8 +68,11 @@ impl TransactionMessage {
             TransactionMessage::Prepare { transaction } => {
                 let mut result = vec![b'P'];
                 result.extend_from_slice(&u32::to_le_bytes(transaction.id));
-                result.extend_from_slice(&u32::to_le_bytes(transaction.airline_price));
-                result.extend_from_slice(&u32::to_le_bytes(transaction.hotel_price));
+                result
+                    .extend_from_slice(&u64::to_le_bytes(transaction.airline_price.minor_units()));
+                result.extend_from_slice(&transaction.airline_currency.code());
+                result.extend_from_slice(&u64::to_le_bytes(transaction.hotel_price.minor_units()));
+                result.extend_from_slice(&transaction.hotel_currency.code());
                 let client_bytes = transaction.client.as_bytes();
                 result.extend(client_bytes.iter());
                 result
@@ -47,30 +94,97 @@ impl TransactionMessage {
                     vec![b'R', b'f']
                 }
             }
+            TransactionMessage::Rejected { reason } => {
+                let mut result = vec![b'J'];
+                result.extend(reason.as_bytes().iter());
+                result
+            }
+            TransactionMessage::UnknownTransaction { transaction_id } => {
+                let mut result = vec![b'U'];
+                result.extend_from_slice(&u32::to_le_bytes(*transaction_id));
+                result
+            }
+            TransactionMessage::PrepareCancellation { transaction_id } => {
+                let mut result = vec![b'Q'];
+                result.extend_from_slice(&u32::to_le_bytes(*transaction_id));
+                result
+            }
+            TransactionMessage::CommitCancellation { transaction_id } => {
+                let mut result = vec![b'K'];
+                result.extend_from_slice(&u32::to_le_bytes(*transaction_id));
+                result
+            }
+            TransactionMessage::AbortCancellation { transaction_id } => {
+                let mut result = vec![b'N'];
+                result.extend_from_slice(&u32::to_le_bytes(*transaction_id));
+                result
+            }
         }
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        match bytes[0] {
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid transaction message: {:?}", bytes),
+            )
+        };
+        let field = |start: usize, end: usize| bytes.get(start..end).ok_or_else(invalid);
+        let u64_at = |start: usize| -> io::Result<u64> {
+            Ok(u64::from_le_bytes(
+                field(start, start + 8)?.try_into().unwrap(),
+            ))
+        };
+        let currency_at = |start: usize| {
+            Currency::from_code(field(start, start + 3)?.try_into().unwrap()).ok_or_else(invalid)
+        };
+        // Transaction ids are the only field of the message, so they must end it.
+        let transaction_id = || -> io::Result<u32> {
+            if bytes.len() != 5 {
+                return Err(invalid());
+            }
+            Ok(u32::from_le_bytes(field(1, 5)?.try_into().unwrap()))
+        };
+        let text_at = |start: usize| -> io::Result<String> {
+            Ok(String::from_utf8_lossy(field(start, bytes.len())?).into())
+        };
+        Ok(match bytes.first().ok_or_else(invalid)? {
             b'P' => TransactionMessage::Prepare {
                 transaction: AlgloboTransaction {
-                    id: u32::from_le_bytes(bytes[1..5].try_into().unwrap()),
-                    airline_price: u32::from_le_bytes(bytes[5..9].try_into().unwrap()),
-                    hotel_price: u32::from_le_bytes(bytes[9..13].try_into().unwrap()),
-                    client: String::from_utf8_lossy(&bytes[13..]).into(),
+                    id: u32::from_le_bytes(field(1, 5)?.try_into().unwrap()),
+                    airline_price: Money::from_minor_units(u64_at(5)?),
+                    airline_currency: currency_at(13)?,
+                    hotel_price: Money::from_minor_units(u64_at(16)?),
+                    hotel_currency: currency_at(24)?,
+                    client: text_at(27)?,
                 },
             },
             b'A' => TransactionMessage::Abort {
-                transaction_id: u32::from_le_bytes(bytes[1..].try_into().unwrap()),
+                transaction_id: transaction_id()?,
             },
             b'C' => TransactionMessage::Commit {
-                transaction_id: u32::from_le_bytes(bytes[1..].try_into().unwrap()),
+                transaction_id: transaction_id()?,
             },
             b'R' => TransactionMessage::Response {
-                success: bytes[1] == b't',
+                success: *bytes.get(1).ok_or_else(invalid)? == b't',
             },
-            _ => panic!("Invalid transaction message: {:?}", bytes),
-        }
+            b'J' => TransactionMessage::Rejected {
+                reason: text_at(1)?,
+            },
+            b'U' => TransactionMessage::UnknownTransaction {
+                transaction_id: transaction_id()?,
+            },
+            b'Q' => TransactionMessage::PrepareCancellation {
+                transaction_id: transaction_id()?,
+            },
+            b'K' => TransactionMessage::CommitCancellation {
+                transaction_id: transaction_id()?,
+            },
+            b'N' => TransactionMessage::AbortCancellation {
+                transaction_id: transaction_id()?,
+            },
+            _ => return Err(invalid()),
+        })
     }
 }
 
@@ -83,28 +197,102 @@ mod tests {
         let msg = TransactionMessage::Prepare {
             transaction: AlgloboTransaction {
                 id: 1234,
-                airline_price: 2,
-                hotel_price: 3,
+                airline_price: Money::from_minor_units(u64::MAX),
+                airline_currency: "USD".parse().unwrap(),
+                hotel_price: Money::from_minor_units(3),
+                hotel_currency: "EUR".parse().unwrap(),
                 client: "test-client".into(),
             },
         };
 
-        assert_eq!(TransactionMessage::from_bytes(&msg.to_bytes()), msg);
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
 
         let msg = TransactionMessage::Commit {
             transaction_id: 99999,
         };
-        assert_eq!(TransactionMessage::from_bytes(&msg.to_bytes()), msg);
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
 
         let msg = TransactionMessage::Abort {
             transaction_id: 1234556,
         };
-        assert_eq!(TransactionMessage::from_bytes(&msg.to_bytes()), msg);
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
 
         let msg = TransactionMessage::Response { success: true };
-        assert_eq!(TransactionMessage::from_bytes(&msg.to_bytes()), msg);
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
 
         let msg = TransactionMessage::Response { success: false };
-        assert_eq!(TransactionMessage::from_bytes(&msg.to_bytes()), msg);
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
+
+        let msg = TransactionMessage::Rejected {
+            reason: "conflict".into(),
+        };
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
+
+        let msg = TransactionMessage::UnknownTransaction { transaction_id: 31 };
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
+
+        let msg = TransactionMessage::PrepareCancellation { transaction_id: 32 };
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
+
+        let msg = TransactionMessage::CommitCancellation { transaction_id: 33 };
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
+
+        let msg = TransactionMessage::AbortCancellation { transaction_id: 34 };
+        assert_eq!(
+            TransactionMessage::from_bytes(&msg.to_bytes()).unwrap(),
+            msg
+        );
+    }
+
+    #[test]
+    fn test_invalid_messages_are_errors() {
+        let prepare = TransactionMessage::Prepare {
+            transaction: AlgloboTransaction {
+                id: 1,
+                airline_price: Money::from_minor_units(1),
+                airline_currency: "USD".parse().unwrap(),
+                hotel_price: Money::from_minor_units(1),
+                hotel_currency: "EUR".parse().unwrap(),
+                client: "test-client".into(),
+            },
+        }
+        .to_bytes();
+        let mut bad_currency = prepare.clone();
+        bad_currency[13] = b'u';
+
+        assert!(TransactionMessage::from_bytes(&[]).is_err());
+        assert!(TransactionMessage::from_bytes(b"Z").is_err());
+        assert!(TransactionMessage::from_bytes(b"C12").is_err());
+        assert!(TransactionMessage::from_bytes(b"C12345").is_err());
+        assert!(TransactionMessage::from_bytes(b"R").is_err());
+        assert!(TransactionMessage::from_bytes(&prepare[..20]).is_err());
+        assert!(TransactionMessage::from_bytes(&bad_currency).is_err());
     }
 }
@@ -0,0 +1,347 @@
+use crate::protocol::Protocol;
+use crate::TransactionMessage;
+use actix::{Message, Recipient};
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A message applied by the primary, to be applied the same way by a standby.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct Replicated {
+    pub message: TransactionMessage,
+    /// When the primary applied it, so the standby records the same time.
+    pub timestamp_ms: u64,
+}
+
+impl Replicated {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut result = self.timestamp_ms.to_le_bytes().to_vec();
+        result.extend(self.message.to_bytes());
+        result
+    }
+
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let timestamp = bytes
+            .get(..8)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))?;
+        Ok(Self {
+            timestamp_ms: u64::from_le_bytes(timestamp.try_into().unwrap()),
+            message: TransactionMessage::from_bytes(&bytes[8..])?,
+        })
+    }
+}
+
+/// Asks the primary for the messages it applied after the position `from`
+/// of its `epoch`, followed by the ones it applies from now on.
+#[derive(Debug, Message)]
+#[rtype(result = "Subscription")]
+pub struct Subscribe {
+    pub epoch: u64,
+    pub from: u64,
+}
+
+/// What the primary ships to a standby that subscribed.
+pub struct Subscription {
+    /// Chosen by the primary when it was promoted, so a standby can tell
+    /// that its position belongs to another primary.
+    pub epoch: u64,
+    /// How many of the first messages rebuild the transaction log from a
+    /// snapshot instead of following the log, because the standby could not
+    /// resume from where it was.
+    pub snapshot: usize,
+    /// Position of the first message after the snapshot.
+    pub from: u64,
+    pub log: UnboundedReceiver<Replicated>,
+}
+
+/// The standby applied every message of the log before the position `upto`,
+/// so the primary no longer needs to keep them.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Acknowledge {
+    pub upto: u64,
+}
+
+/// Turns a standby into the primary.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Promote;
+
+/// Ships the log of the primary to every standby that connects to `listener`.
+///
+/// A standby starts by sending the epoch and position it is synced to, as
+/// little endian u64s. The primary answers with its epoch, the position the
+/// log continues from, as u64s, and how many snapshot messages come first, as
+/// a u32. Then it sends each message framed like any other
+/// `TransactionMessage` but preceded by the time it was applied, as a u64, and
+/// the standby acknowledges each one once applied.
+pub async fn serve_replication(
+    listener: TcpListener,
+    subscribe: Recipient<Subscribe>,
+    acknowledge: Recipient<Acknowledge>,
+) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let subscribe = subscribe.clone();
+        let acknowledge = acknowledge.clone();
+        actix_rt::spawn(async move {
+            if let Err(e) = ship(stream, subscribe, acknowledge).await {
+                println!("Standby disconnected: {}", e);
+            }
+        });
+    }
+}
+
+async fn ship(
+    mut stream: TcpStream,
+    subscribe: Recipient<Subscribe>,
+    acknowledge: Recipient<Acknowledge>,
+) -> io::Result<()> {
+    let mut request = [0u8; 16];
+    stream.read_exact(&mut request).await?;
+    let epoch = u64::from_le_bytes(request[..8].try_into().unwrap());
+    let from = u64::from_le_bytes(request[8..].try_into().unwrap());
+    let mut subscription = subscribe
+        .send(Subscribe { epoch, from })
+        .await
+        .map_err(io::Error::other)?;
+
+    let mut header = Vec::with_capacity(20);
+    header.extend_from_slice(&subscription.epoch.to_le_bytes());
+    header.extend_from_slice(&subscription.from.to_le_bytes());
+    header.extend_from_slice(&(subscription.snapshot as u32).to_le_bytes());
+    stream.write_all(&header).await?;
+
+    let mut protocol = Protocol::new(stream);
+    let mut snapshot = subscription.snapshot;
+    let mut position = subscription.from;
+    while let Some(replicated) = subscription.log.recv().await {
+        protocol.send_frame(replicated.to_bytes()).await?;
+        if !matches!(
+            protocol.receive().await,
+            Some(Ok(TransactionMessage::Response { success: true }))
+        ) {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if snapshot > 0 {
+            snapshot -= 1;
+        } else {
+            position += 1;
+            acknowledge.do_send(Acknowledge { upto: position });
+        }
+    }
+    Ok(())
+}
+
+/// The primary and position in its log the standby is synced to.
+#[derive(Default)]
+struct Synced {
+    /// 0 until the standby synced with any primary.
+    epoch: u64,
+    position: u64,
+}
+
+/// Applies the log shipped by the primary at `host` to the local standby,
+/// reconnecting whenever the connection is lost. It returns once the standby
+/// stops, so it is meant to be aborted once this replica is promoted.
+pub async fn replicate_from(host: String, standby: Recipient<Replicated>) {
+    let mut synced = Synced::default();
+    while standby.connected() {
+        if let Ok(stream) = TcpStream::connect(&host).await {
+            println!("Replicating from primary at {}", host);
+            if let Err(e) = follow(stream, &standby, &mut synced).await {
+                println!("Lost connection with primary at {}: {}", host, e);
+            }
+        }
+        actix_rt::time::sleep(RECONNECT_INTERVAL).await;
+    }
+}
+
+async fn follow(
+    mut stream: TcpStream,
+    standby: &Recipient<Replicated>,
+    synced: &mut Synced,
+) -> io::Result<()> {
+    let mut request = Vec::with_capacity(16);
+    request.extend_from_slice(&synced.epoch.to_le_bytes());
+    request.extend_from_slice(&synced.position.to_le_bytes());
+    stream.write_all(&request).await?;
+
+    let mut header = [0u8; 20];
+    stream.read_exact(&mut header).await?;
+    let epoch = u64::from_le_bytes(header[..8].try_into().unwrap());
+    let from = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let mut snapshot = u32::from_le_bytes(header[16..].try_into().unwrap());
+    if snapshot > 0 {
+        println!(
+            "Resyncing from a snapshot of {} messages of primary epoch {}",
+            snapshot, epoch
+        );
+    } else {
+        synced.epoch = epoch;
+        synced.position = from;
+    }
+
+    let mut protocol = Protocol::new(stream);
+    while let Some(frame) = protocol.receive_frame().await {
+        standby
+            .send(Replicated::from_bytes(&frame)?)
+            .await
+            .map_err(io::Error::other)?;
+        if snapshot > 0 {
+            snapshot -= 1;
+            if snapshot == 0 {
+                // Only synced once the whole snapshot was applied.
+                synced.epoch = epoch;
+                synced.position = from;
+            }
+        } else {
+            synced.position += 1;
+        }
+        protocol.send_ok().await?;
+    }
+    Err(io::ErrorKind::UnexpectedEof.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::AdminCommand;
+    use crate::alglobo_transaction::AlgloboTransaction;
+    use crate::money::Money;
+    use crate::participant::{Participant, ParticipantActor};
+    use actix::{Actor, Addr};
+
+    #[derive(Default)]
+    struct Bookings {
+        committed: u32,
+    }
+
+    impl Participant for Bookings {
+        const NAME: &'static str = "BOOKINGS";
+        const ENTITY: u8 = 0;
+
+        fn on_prepare(&mut self, _transaction: &AlgloboTransaction) -> bool {
+            true
+        }
+
+        fn on_commit(&mut self, _transaction: &AlgloboTransaction, _timestamp_ms: u64) {
+            self.committed += 1;
+        }
+
+        fn on_abort(&mut self, _transaction: &AlgloboTransaction) {}
+
+        fn on_cancel(&mut self, _transaction: &AlgloboTransaction, _timestamp_ms: u64) {
+            self.committed -= 1;
+        }
+
+        fn inventory(&self) -> String {
+            format!("committed={}", self.committed)
+        }
+    }
+
+    fn prepare(id: u32) -> TransactionMessage {
+        TransactionMessage::Prepare {
+            transaction: AlgloboTransaction {
+                id,
+                client: "lucho".into(),
+                hotel_price: Money::from_minor_units(10),
+                hotel_currency: "USD".parse().unwrap(),
+                airline_price: Money::from_minor_units(20),
+                airline_currency: "USD".parse().unwrap(),
+            },
+        }
+    }
+
+    async fn apply(primary: &Addr<ParticipantActor<Bookings>>, messages: Vec<TransactionMessage>) {
+        for message in messages {
+            primary.send(message).await.unwrap().unwrap();
+        }
+    }
+
+    async fn describe(replica: &Addr<ParticipantActor<Bookings>>) -> String {
+        let mut description = String::new();
+        for command in ["list", "inventory"] {
+            let words = vec![command.to_string()];
+            description += &replica.send(AdminCommand { words }).await.unwrap();
+        }
+        description
+    }
+
+    /// Waits for the standby to apply everything the primary shipped.
+    async fn assert_synced(
+        primary: &Addr<ParticipantActor<Bookings>>,
+        standby: &Addr<ParticipantActor<Bookings>>,
+    ) {
+        let expected = describe(primary).await;
+        for _ in 0..100 {
+            if describe(standby).await == expected {
+                return;
+            }
+            actix_rt::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(describe(standby).await, expected);
+    }
+
+    #[actix_rt::test]
+    async fn test_standby_follows_the_primary() {
+        let primary = ParticipantActor::new(Bookings::default())
+            .with_replication()
+            .start();
+        primary.send(Promote).await.unwrap();
+        // Applied before the standby subscribes, so it gets them in the snapshot.
+        apply(
+            &primary,
+            vec![
+                prepare(1),
+                TransactionMessage::Commit { transaction_id: 1 },
+                prepare(2),
+                TransactionMessage::Abort { transaction_id: 2 },
+                prepare(3),
+            ],
+        )
+        .await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        actix_rt::spawn(serve_replication(
+            listener,
+            primary.clone().recipient(),
+            primary.clone().recipient(),
+        ));
+        let standby = ParticipantActor::new(Bookings::default())
+            .with_replication()
+            .start();
+        actix_rt::spawn(replicate_from(host, standby.clone().recipient()));
+        assert_synced(&primary, &standby).await;
+
+        // Shipped as the tail of the log.
+        apply(
+            &primary,
+            vec![
+                TransactionMessage::Commit { transaction_id: 3 },
+                prepare(4),
+                TransactionMessage::Abort { transaction_id: 4 },
+                prepare(5),
+                TransactionMessage::PrepareCancellation { transaction_id: 1 },
+                TransactionMessage::CommitCancellation { transaction_id: 1 },
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            describe(&primary).await,
+            "1 cancelled client=lucho hotel_price=0.10 USD airline_price=0.20 USD\n\
+             2 aborted client=lucho hotel_price=0.10 USD airline_price=0.20 USD\n\
+             3 committed client=lucho hotel_price=0.10 USD airline_price=0.20 USD\n\
+             4 aborted client=lucho hotel_price=0.10 USD airline_price=0.20 USD\n\
+             5 prepared client=lucho hotel_price=0.10 USD airline_price=0.20 USD\n\
+             committed=1\n"
+        );
+        assert_synced(&primary, &standby).await;
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// An amount of money in minor units (cents for USD) of some currency.
+///
+/// Operations are checked, so an amount that does not fit is reported as a
+/// `MoneyError` instead of panicking or wrapping around.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Money(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoneyError {
+    Overflow,
+    /// The result would be negative.
+    Underflow,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::Overflow => f.write_str("amount overflow"),
+            MoneyError::Underflow => f.write_str("amount underflow"),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_minor_units(minor_units: u64) -> Self {
+        Self(minor_units)
+    }
+
+    pub const fn minor_units(&self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.0
+            .checked_add(other.0)
+            .map(Money)
+            .ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.0
+            .checked_sub(other.0)
+            .map(Money)
+            .ok_or(MoneyError::Underflow)
+    }
+
+    /// Adds up `amounts`, failing if the total does not fit.
+    pub fn checked_sum<I: IntoIterator<Item = Money>>(amounts: I) -> Result<Money, MoneyError> {
+        amounts
+            .into_iter()
+            .try_fold(Money::ZERO, |total, amount| total.checked_add(amount))
+    }
+
+    /// Signed amount, as used for balances.
+    pub fn to_signed(self) -> Result<i64, MoneyError> {
+        i64::try_from(self.0).map_err(|_| MoneyError::Overflow)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_operations() {
+        let max = Money::from_minor_units(u64::MAX);
+        let one = Money::from_minor_units(1);
+        assert_eq!(max.checked_add(one), Err(MoneyError::Overflow));
+        assert_eq!(Money::ZERO.checked_sub(one), Err(MoneyError::Underflow));
+        assert_eq!(one.checked_add(one), Ok(Money::from_minor_units(2)));
+        assert_eq!(Money::checked_sum([max, Money::ZERO]), Ok(max));
+        assert_eq!(Money::checked_sum([max, one]), Err(MoneyError::Overflow));
+        assert_eq!(max.to_signed(), Err(MoneyError::Overflow));
+    }
+}
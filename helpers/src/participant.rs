@@ -0,0 +1,1185 @@
+use crate::admin::{AdminCommand, ADMIN_HELP};
+use crate::alglobo_transaction::AlgloboTransaction;
+use crate::event::{
+    now_ms, Event, EventKind, EventSource, COORDINATOR_ABORT, PREPARE_CONFLICT, PREPARE_REJECTED,
+};
+use crate::log_shipping::{Acknowledge, Promote, Replicated, Subscribe, Subscription};
+use crate::stats_client::StatsClient;
+use crate::TransactionMessage;
+use actix::{Actor, ActorContext, AsyncContext, Context, Handler, Message, MessageResult};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+/// Business logic of an entity taking part in AlGlobo's two phase commit.
+///
+/// Implementors only decide what preparing, committing and aborting means for
+/// them; `ParticipantActor` takes care of the transaction log, idempotency,
+/// logging and replies.
+pub trait Participant: Unpin + 'static {
+    /// Name used to prefix the log lines of this participant.
+    const NAME: &'static str;
+
+    /// Id of this participant in the events it emits.
+    const ENTITY: u8;
+
+    /// Votes on a new transaction. Returning `false` rejects it.
+    fn on_prepare(&mut self, transaction: &AlgloboTransaction) -> bool;
+
+    /// Applies a transaction that was previously prepared. `timestamp_ms` is
+    /// when the primary committed it, the same on every replica.
+    fn on_commit(&mut self, transaction: &AlgloboTransaction, timestamp_ms: u64);
+
+    /// Releases whatever was held for a transaction that was previously prepared.
+    fn on_abort(&mut self, transaction: &AlgloboTransaction);
+
+    /// Votes on cancelling a committed transaction. Returning `false` keeps
+    /// it committed.
+    fn on_prepare_cancellation(&mut self, _transaction: &AlgloboTransaction) -> bool {
+        true
+    }
+
+    /// Undoes a committed transaction whose cancellation was prepared.
+    /// `timestamp_ms` is when the primary cancelled it.
+    fn on_cancel(&mut self, transaction: &AlgloboTransaction, timestamp_ms: u64);
+
+    /// Releases whatever was held to cancel a transaction that stays committed.
+    fn on_abort_cancellation(&mut self, _transaction: &AlgloboTransaction) {}
+
+    /// Human readable description of the balances or inventory of the
+    /// participant, shown by the admin `inventory` command.
+    fn inventory(&self) -> String;
+
+    /// Handles an admin command that is specific to this participant.
+    /// Returns `None` if the command is unknown.
+    fn admin(&mut self, _words: &[&str]) -> Option<String> {
+        None
+    }
+
+    /// Persists whatever state the participant keeps before the entity exits.
+    fn flush(&mut self) {}
+}
+
+/// Messages kept for a standby that has not acknowledged them. One further
+/// behind resyncs from a snapshot instead.
+const MAX_UNACKNOWLEDGED: usize = 10_000;
+
+/// Asks the participant to flush its state and stop.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Shutdown;
+
+enum TransactionState {
+    Prepared {
+        tx: AlgloboTransaction,
+    },
+    Committed {
+        tx: AlgloboTransaction,
+    },
+    Aborted {
+        tx: Option<AlgloboTransaction>,
+    },
+    /// Committed, waiting for the decision on its cancellation.
+    CancelPrepared {
+        tx: AlgloboTransaction,
+    },
+    Cancelled {
+        tx: AlgloboTransaction,
+    },
+}
+
+impl TransactionState {
+    fn name(&self) -> &'static str {
+        match self {
+            TransactionState::Prepared { .. } => "prepared",
+            TransactionState::Committed { .. } => "committed",
+            TransactionState::Aborted { .. } => "aborted",
+            TransactionState::CancelPrepared { .. } => "cancel-prepared",
+            TransactionState::Cancelled { .. } => "cancelled",
+        }
+    }
+
+    fn transaction(&self) -> Option<&AlgloboTransaction> {
+        match self {
+            TransactionState::Prepared { tx }
+            | TransactionState::Committed { tx }
+            | TransactionState::CancelPrepared { tx }
+            | TransactionState::Cancelled { tx } => Some(tx),
+            TransactionState::Aborted { tx } => tx.as_ref(),
+        }
+    }
+}
+
+/// When a commit or a cancellation was applied.
+#[derive(Debug, Clone, Copy)]
+struct Decision {
+    /// Order among the decisions applied, so a snapshot replays them in the
+    /// same order.
+    sequence: u64,
+    /// When the primary applied it.
+    timestamp_ms: u64,
+}
+
+/// A decision that was refused because the transaction had already finished
+/// with the opposite outcome.
+struct AuditEntry {
+    timestamp: SystemTime,
+    transaction_id: u32,
+    state: &'static str,
+    operation: &'static str,
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        write!(
+            f,
+            "{} {} of transaction {} refused, it was already {}",
+            seconds, self.operation, self.transaction_id, self.state
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    prepared: u64,
+    rejected: u64,
+    conflicts: u64,
+    committed: u64,
+    aborted: u64,
+    expired: u64,
+    unknown: u64,
+    illegal_transitions: u64,
+    cancel_prepared: u64,
+    cancel_rejected: u64,
+    cancelled: u64,
+}
+
+pub struct ParticipantActor<P: Participant> {
+    participant: P,
+    transaction_log: HashMap<u32, TransactionState>,
+    /// Fingerprint of the payload each transaction was prepared with.
+    fingerprints: HashMap<u32, u64>,
+    /// When each transaction still waiting for a decision was prepared.
+    prepared_at: HashMap<u32, Instant>,
+    commits: HashMap<u32, Decision>,
+    cancellations: HashMap<u32, Decision>,
+    next_decision: u64,
+    /// Generation of the expiry timer armed last for each transaction, so
+    /// the timer of its prepare cannot expire a later cancellation.
+    expiries: HashMap<u32, u64>,
+    next_expiry: u64,
+    counters: Counters,
+    audit_trail: Vec<AuditEntry>,
+    prepare_ttl: Option<Duration>,
+    stats: Option<StatsClient>,
+    /// Whether the decisions applied are shipped to a standby.
+    replicated: bool,
+    /// A standby only applies what its primary ships until it is promoted.
+    standby: bool,
+    /// Chosen when promoted, numbers the positions of `replication_log`.
+    epoch: u64,
+    /// Messages applied since the first one a standby did not acknowledge,
+    /// so a standby that reconnects can resume from where it was.
+    replication_log: VecDeque<Replicated>,
+    /// Position of the first message of `replication_log`.
+    replication_start: u64,
+    replicas: Vec<UnboundedSender<Replicated>>,
+}
+
+impl<P: Participant> ParticipantActor<P> {
+    pub fn new(participant: P) -> Self {
+        Self {
+            participant,
+            transaction_log: HashMap::new(),
+            fingerprints: HashMap::new(),
+            prepared_at: HashMap::new(),
+            commits: HashMap::new(),
+            cancellations: HashMap::new(),
+            next_decision: 0,
+            expiries: HashMap::new(),
+            next_expiry: 0,
+            counters: Counters::default(),
+            audit_trail: Vec::new(),
+            prepare_ttl: None,
+            stats: None,
+            replicated: false,
+            standby: false,
+            epoch: 0,
+            replication_log: VecDeque::new(),
+            replication_start: 0,
+            replicas: Vec::new(),
+        }
+    }
+
+    /// Ships every applied message to the standbys once promoted, and starts
+    /// as a standby itself until it receives `Promote`.
+    pub fn with_replication(mut self) -> Self {
+        self.replicated = true;
+        self.standby = true;
+        self
+    }
+
+    /// Presumes abort of transactions that are still prepared after `ttl`,
+    /// so a coordinator that never decides does not hold our resources forever.
+    ///
+    /// The TTL must be much longer than a normal payment round, otherwise a
+    /// late commit would find the transaction already aborted.
+    pub fn with_prepare_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.prepare_ttl = ttl;
+        self
+    }
+
+    /// Reports the events produced by this participant to stats.
+    pub fn with_stats(mut self, stats: StatsClient) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    fn emit(&self, transaction_id: u32, kind: EventKind) {
+        if self.standby {
+            // The primary already reported it.
+            return;
+        }
+        let event = Event::new(EventSource::Entity(P::ENTITY), transaction_id, kind);
+        println!("[{}] event: {:?}", P::NAME, event);
+        if let Some(stats) = &self.stats {
+            stats.send(event);
+        }
+    }
+
+    /// Votes on `transaction` and returns the reply for the coordinator.
+    ///
+    /// Preparing an id that is already in the log is idempotent as long as the
+    /// payload is the same one and it is still prepared or committed; a
+    /// different payload is rejected as a conflict. An aborted or cancelled
+    /// transaction cannot be prepared again, otherwise a coordinator retrying
+    /// after we presumed abort would commit it elsewhere but not here.
+    fn prepare(&mut self, transaction: AlgloboTransaction) -> TransactionMessage {
+        let fingerprint = transaction.fingerprint();
+        if let Some(state) = self.transaction_log.get(&transaction.id) {
+            if !matches!(
+                state,
+                TransactionState::Prepared { .. } | TransactionState::Committed { .. }
+            ) {
+                let name = state.name();
+                return self.illegal_transition(transaction.id, name, "prepare");
+            }
+            return match self.fingerprints.get(&transaction.id) {
+                // Transaction is already in the log, so it was already prepared.
+                Some(known) if *known == fingerprint => {
+                    TransactionMessage::Response { success: true }
+                }
+                _ => {
+                    println!(
+                        "[{}] Conflicting prepare for transaction {}: {:?}",
+                        P::NAME,
+                        transaction.id,
+                        transaction
+                    );
+                    self.counters.conflicts += 1;
+                    let reason = format!(
+                        "transaction {} was already prepared with a different payload",
+                        transaction.id
+                    );
+                    self.emit(
+                        transaction.id,
+                        EventKind::TxFailure {
+                            entity: P::ENTITY,
+                            code: PREPARE_CONFLICT,
+                            reason: reason.clone(),
+                        },
+                    );
+                    TransactionMessage::Rejected { reason }
+                }
+            };
+        }
+
+        if !self.participant.on_prepare(&transaction) {
+            self.counters.rejected += 1;
+            self.emit(
+                transaction.id,
+                EventKind::TxFailure {
+                    entity: P::ENTITY,
+                    code: PREPARE_REJECTED,
+                    reason: "prepare rejected".into(),
+                },
+            );
+            return TransactionMessage::Response { success: false };
+        }
+        self.counters.prepared += 1;
+        self.fingerprints.insert(transaction.id, fingerprint);
+        self.prepared_at.insert(transaction.id, Instant::now());
+        self.transaction_log.insert(
+            transaction.id,
+            TransactionState::Prepared { tx: transaction },
+        );
+        TransactionMessage::Response { success: true }
+    }
+
+    /// Applies a prepare, commit or abort decided at `timestamp_ms` and
+    /// returns the reply for the coordinator.
+    fn apply(
+        &mut self,
+        msg: TransactionMessage,
+        timestamp_ms: u64,
+        ctx: &mut Context<Self>,
+    ) -> Option<TransactionMessage> {
+        let reply = match msg.clone() {
+            TransactionMessage::Prepare { transaction } => {
+                let transaction_id = transaction.id;
+                let already_prepared = self.transaction_log.contains_key(&transaction_id);
+                let reply = self.prepare(transaction);
+                if !already_prepared && reply == (TransactionMessage::Response { success: true }) {
+                    self.schedule_expiry(transaction_id, ctx);
+                }
+                reply
+            }
+            TransactionMessage::Commit { transaction_id } => {
+                self.commit(transaction_id, timestamp_ms)
+            }
+            TransactionMessage::Abort { transaction_id } => self.abort(transaction_id),
+            TransactionMessage::PrepareCancellation { transaction_id } => {
+                let was_committed = matches!(
+                    self.transaction_log.get(&transaction_id),
+                    Some(TransactionState::Committed { .. })
+                );
+                let reply = self.prepare_cancellation(transaction_id);
+                if was_committed && reply == (TransactionMessage::Response { success: true }) {
+                    self.schedule_expiry(transaction_id, ctx);
+                }
+                reply
+            }
+            TransactionMessage::CommitCancellation { transaction_id } => {
+                self.commit_cancellation(transaction_id, timestamp_ms)
+            }
+            TransactionMessage::AbortCancellation { transaction_id } => {
+                self.abort_cancellation(transaction_id)
+            }
+            TransactionMessage::Response { .. }
+            | TransactionMessage::Rejected { .. }
+            | TransactionMessage::UnknownTransaction { .. } => {
+                println!("[{}] Ignoring unexpected response", P::NAME);
+                return None;
+            }
+        };
+        self.ship(msg, timestamp_ms);
+        Some(reply)
+    }
+
+    /// Sends an applied message to the standby replicas.
+    fn ship(&mut self, message: TransactionMessage, timestamp_ms: u64) {
+        if !self.replicated || self.standby {
+            return;
+        }
+        let replicated = Replicated {
+            message,
+            timestamp_ms,
+        };
+        self.replicas
+            .retain(|replica| replica.send(replicated.clone()).is_ok());
+        self.replication_log.push_back(replicated);
+        if self.replication_log.len() > MAX_UNACKNOWLEDGED {
+            self.replication_log.pop_front();
+            self.replication_start += 1;
+        }
+    }
+
+    /// Messages that take a standby to the current state of every transaction
+    /// in the log. Applying them is idempotent, so the standby may already
+    /// have some of them applied.
+    ///
+    /// Commits and cancellations come last, in the order they were applied,
+    /// so the participant of the standby sees them in the same order.
+    fn snapshot(&self) -> Vec<Replicated> {
+        let mut ids: Vec<_> = self.transaction_log.keys().copied().collect();
+        ids.sort_unstable();
+        let mut undecided = Vec::new();
+        let mut decisions = Vec::new();
+        for transaction_id in ids {
+            let state = &self.transaction_log[&transaction_id];
+            if let Some(tx) = state.transaction() {
+                undecided.push(TransactionMessage::Prepare {
+                    transaction: tx.clone(),
+                });
+            }
+            let commit = self.commits.get(&transaction_id).copied();
+            let cancellation = self.cancellations.get(&transaction_id).copied();
+            match state {
+                TransactionState::Prepared { .. } => {}
+                TransactionState::Aborted { .. } => {
+                    undecided.push(TransactionMessage::Abort { transaction_id })
+                }
+                TransactionState::Committed { .. } => decisions.push((
+                    commit,
+                    vec![
+                        TransactionMessage::Commit { transaction_id },
+                        TransactionMessage::AbortCancellation { transaction_id },
+                    ],
+                )),
+                TransactionState::CancelPrepared { .. } => decisions.push((
+                    commit,
+                    vec![
+                        TransactionMessage::Commit { transaction_id },
+                        TransactionMessage::PrepareCancellation { transaction_id },
+                    ],
+                )),
+                TransactionState::Cancelled { .. } => {
+                    decisions.push((commit, vec![TransactionMessage::Commit { transaction_id }]));
+                    decisions.push((
+                        cancellation,
+                        vec![
+                            TransactionMessage::PrepareCancellation { transaction_id },
+                            TransactionMessage::CommitCancellation { transaction_id },
+                        ],
+                    ));
+                }
+            }
+        }
+        decisions.sort_by_key(|(decision, _)| decision.map(|decision| decision.sequence));
+
+        // Only the time of the decisions matters to the participant.
+        let mut messages: Vec<Replicated> = undecided
+            .into_iter()
+            .map(|message| Replicated {
+                message,
+                timestamp_ms: 0,
+            })
+            .collect();
+        for (decision, decided) in decisions {
+            let timestamp_ms = decision.map_or(0, |decision| decision.timestamp_ms);
+            messages.extend(decided.into_iter().map(|message| Replicated {
+                message,
+                timestamp_ms,
+            }));
+        }
+        messages
+    }
+
+    /// Records a commit or cancellation applied now, decided at `timestamp_ms`.
+    fn decide(&mut self, timestamp_ms: u64) -> Decision {
+        self.next_decision += 1;
+        Decision {
+            sequence: self.next_decision,
+            timestamp_ms,
+        }
+    }
+
+    fn schedule_expiry(&mut self, transaction_id: u32, ctx: &mut Context<Self>) {
+        if self.standby {
+            // Only the primary decides when a transaction expired.
+            return;
+        }
+        if let Some(ttl) = self.prepare_ttl {
+            let generation = self.arm_expiry(transaction_id);
+            ctx.run_later(ttl, move |act, _ctx| act.expire(transaction_id, generation));
+        }
+    }
+
+    /// Replaces the expiry timer of `transaction_id` with a new generation.
+    fn arm_expiry(&mut self, transaction_id: u32) -> u64 {
+        self.next_expiry += 1;
+        self.expiries.insert(transaction_id, self.next_expiry);
+        self.next_expiry
+    }
+
+    /// Fired by the timer of `generation`, which does nothing if another timer
+    /// was armed for the transaction since.
+    fn expire(&mut self, transaction_id: u32, generation: u64) {
+        if self.expiries.get(&transaction_id) != Some(&generation) {
+            return;
+        }
+        self.expiries.remove(&transaction_id);
+        match self.transaction_log.get(&transaction_id) {
+            Some(TransactionState::Prepared { .. }) => {
+                println!(
+                    "[{}] Transaction {} expired without a decision, presuming abort",
+                    P::NAME,
+                    transaction_id
+                );
+                self.release(transaction_id);
+                self.ship(TransactionMessage::Abort { transaction_id }, now_ms());
+                self.counters.expired += 1;
+                self.emit(transaction_id, EventKind::TxExpired { entity: P::ENTITY });
+            }
+            Some(TransactionState::CancelPrepared { .. }) => {
+                println!(
+                    "[{}] Cancellation of transaction {} expired without a decision, keeping it committed",
+                    P::NAME,
+                    transaction_id
+                );
+                self.abort_cancellation(transaction_id);
+                self.ship(
+                    TransactionMessage::AbortCancellation { transaction_id },
+                    now_ms(),
+                );
+                self.counters.expired += 1;
+                self.emit(transaction_id, EventKind::TxExpired { entity: P::ENTITY });
+            }
+            _ => {}
+        }
+    }
+
+    fn commit(&mut self, transaction_id: u32, timestamp_ms: u64) -> TransactionMessage {
+        let state = match self.transaction_log.remove(&transaction_id) {
+            Some(state) => state,
+            None => return self.unknown_transaction(transaction_id),
+        };
+        if let TransactionState::Prepared { tx } = state {
+            self.participant.on_commit(&tx, timestamp_ms);
+            let decision = self.decide(timestamp_ms);
+            self.commits.insert(transaction_id, decision);
+            self.counters.committed += 1;
+            self.transaction_log
+                .insert(transaction_id, TransactionState::Committed { tx });
+            if let Some(prepared_at) = self.prepared_at.remove(&transaction_id) {
+                self.emit(
+                    transaction_id,
+                    EventKind::TxSuccess {
+                        entity: P::ENTITY,
+                        duration_ms: prepared_at.elapsed().as_millis() as u32,
+                    },
+                );
+            }
+        } else {
+            let name = state.name();
+            let is_committed = matches!(state, TransactionState::Committed { .. });
+            self.transaction_log.insert(transaction_id, state);
+            if !is_committed {
+                return self.illegal_transition(transaction_id, name, "commit");
+            }
+            // Already committed
+        }
+        TransactionMessage::Response { success: true }
+    }
+
+    fn abort(&mut self, transaction_id: u32) -> TransactionMessage {
+        match self.transaction_log.get(&transaction_id) {
+            Some(
+                state @ (TransactionState::Committed { .. }
+                | TransactionState::CancelPrepared { .. }
+                | TransactionState::Cancelled { .. }),
+            ) => {
+                let name = state.name();
+                return self.illegal_transition(transaction_id, name, "abort");
+            }
+            // Already finished
+            Some(TransactionState::Aborted { .. }) => {}
+            Some(TransactionState::Prepared { .. }) => {
+                self.release(transaction_id);
+                self.emit(
+                    transaction_id,
+                    EventKind::TxFailure {
+                        entity: P::ENTITY,
+                        code: COORDINATOR_ABORT,
+                        reason: "aborted by coordinator".into(),
+                    },
+                );
+            }
+            None => {
+                // The abort is still recorded, so a late prepare for this id is refused.
+                self.release(transaction_id);
+                return self.unknown_transaction(transaction_id);
+            }
+        }
+        TransactionMessage::Response { success: true }
+    }
+
+    /// Votes on cancelling a committed transaction and returns the reply for
+    /// the coordinator. Preparing the same cancellation again is idempotent.
+    fn prepare_cancellation(&mut self, transaction_id: u32) -> TransactionMessage {
+        let tx = match self.transaction_log.get(&transaction_id) {
+            Some(TransactionState::Committed { tx }) => tx.clone(),
+            Some(TransactionState::CancelPrepared { .. } | TransactionState::Cancelled { .. }) => {
+                return TransactionMessage::Response { success: true }
+            }
+            Some(state) => {
+                let name = state.name();
+                return self.illegal_transition(transaction_id, name, "cancel");
+            }
+            None => return self.unknown_transaction(transaction_id),
+        };
+        if !self.participant.on_prepare_cancellation(&tx) {
+            self.counters.cancel_rejected += 1;
+            return TransactionMessage::Response { success: false };
+        }
+        self.counters.cancel_prepared += 1;
+        self.transaction_log
+            .insert(transaction_id, TransactionState::CancelPrepared { tx });
+        TransactionMessage::Response { success: true }
+    }
+
+    fn commit_cancellation(
+        &mut self,
+        transaction_id: u32,
+        timestamp_ms: u64,
+    ) -> TransactionMessage {
+        match self.transaction_log.remove(&transaction_id) {
+            Some(TransactionState::CancelPrepared { tx }) => {
+                self.participant.on_cancel(&tx, timestamp_ms);
+                let decision = self.decide(timestamp_ms);
+                self.cancellations.insert(transaction_id, decision);
+                self.counters.cancelled += 1;
+                self.transaction_log
+                    .insert(transaction_id, TransactionState::Cancelled { tx });
+                TransactionMessage::Response { success: true }
+            }
+            Some(state @ TransactionState::Cancelled { .. }) => {
+                // Already cancelled
+                self.transaction_log.insert(transaction_id, state);
+                TransactionMessage::Response { success: true }
+            }
+            Some(state) => {
+                let name = state.name();
+                self.transaction_log.insert(transaction_id, state);
+                self.illegal_transition(transaction_id, name, "commit cancellation")
+            }
+            None => self.unknown_transaction(transaction_id),
+        }
+    }
+
+    fn abort_cancellation(&mut self, transaction_id: u32) -> TransactionMessage {
+        match self.transaction_log.remove(&transaction_id) {
+            Some(TransactionState::CancelPrepared { tx }) => {
+                self.participant.on_abort_cancellation(&tx);
+                self.transaction_log
+                    .insert(transaction_id, TransactionState::Committed { tx });
+                TransactionMessage::Response { success: true }
+            }
+            Some(state @ TransactionState::Committed { .. }) => {
+                // The cancellation was never prepared or was already aborted
+                self.transaction_log.insert(transaction_id, state);
+                TransactionMessage::Response { success: true }
+            }
+            Some(state) => {
+                let name = state.name();
+                self.transaction_log.insert(transaction_id, state);
+                self.illegal_transition(transaction_id, name, "abort cancellation")
+            }
+            None => self.unknown_transaction(transaction_id),
+        }
+    }
+
+    /// Refuses a decision that would move a finished transaction to another
+    /// final state, and records the attempt in the audit trail.
+    ///
+    /// A standby only refuses the messages of a snapshot that it already
+    /// applied, so it does not record them.
+    fn illegal_transition(
+        &mut self,
+        transaction_id: u32,
+        state: &'static str,
+        operation: &'static str,
+    ) -> TransactionMessage {
+        let reason = format!(
+            "cannot {} transaction {} because it is already {}",
+            operation, transaction_id, state
+        );
+        if self.standby {
+            return TransactionMessage::Rejected { reason };
+        }
+        let entry = AuditEntry {
+            timestamp: SystemTime::now(),
+            transaction_id,
+            state,
+            operation,
+        };
+        println!("[{}] Illegal transition: {}", P::NAME, entry);
+        self.counters.illegal_transitions += 1;
+        self.audit_trail.push(entry);
+        TransactionMessage::Rejected { reason }
+    }
+
+    fn unknown_transaction(&mut self, transaction_id: u32) -> TransactionMessage {
+        println!(
+            "[{}] Decision for unknown transaction {}",
+            P::NAME,
+            transaction_id
+        );
+        self.counters.unknown += 1;
+        TransactionMessage::UnknownTransaction { transaction_id }
+    }
+
+    /// Moves a prepared (or unknown) transaction to aborted, releasing what
+    /// was held for it.
+    fn release(&mut self, transaction_id: u32) {
+        self.prepared_at.remove(&transaction_id);
+        let tx = match self.transaction_log.get(&transaction_id) {
+            Some(TransactionState::Prepared { tx }) => {
+                self.participant.on_abort(tx);
+                Some(tx.clone())
+            }
+            Some(_) => panic!("Transaction {} has already finished", transaction_id),
+            None => None,
+        };
+        self.counters.aborted += 1;
+        self.transaction_log
+            .insert(transaction_id, TransactionState::Aborted { tx });
+    }
+
+    fn describe(transaction_id: u32, state: &TransactionState) -> String {
+        match state.transaction() {
+            Some(tx) => format!(
+                "{} {} client={} hotel_price={} airline_price={}",
+                transaction_id,
+                state.name(),
+                tx.client,
+                tx.hotel_currency.format(tx.hotel_price),
+                tx.airline_currency.format(tx.airline_price)
+            ),
+            None => format!("{} {}", transaction_id, state.name()),
+        }
+    }
+
+    fn list(&self, state: Option<&str>) -> String {
+        let mut ids: Vec<_> = self
+            .transaction_log
+            .iter()
+            .filter(|(_, s)| state.is_none_or(|state| s.name() == state))
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort_unstable();
+        ids.iter()
+            .map(|id| Self::describe(*id, &self.transaction_log[id]) + "\n")
+            .collect()
+    }
+}
+
+impl<P: Participant> Actor for ParticipantActor<P> {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Context<Self>) {
+        println!("[{}] Iniciado", P::NAME);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Context<Self>) {
+        println!("[{}] Detenido", P::NAME);
+    }
+}
+
+impl<P: Participant> Handler<TransactionMessage> for ParticipantActor<P> {
+    type Result = Result<Option<TransactionMessage>, std::io::Error>;
+
+    fn handle(&mut self, msg: TransactionMessage, ctx: &mut Context<Self>) -> Self::Result {
+        println!("[{}] handle: {:?}", P::NAME, msg);
+        if self.standby {
+            return Ok(Some(TransactionMessage::Rejected {
+                reason: format!("{} is a standby replica", P::NAME),
+            }));
+        }
+        Ok(self.apply(msg, now_ms(), ctx))
+    }
+}
+
+impl<P: Participant> Handler<Replicated> for ParticipantActor<P> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Replicated, ctx: &mut Context<Self>) -> Self::Result {
+        println!("[{}] replicated: {:?}", P::NAME, msg.message);
+        self.apply(msg.message, msg.timestamp_ms, ctx);
+    }
+}
+
+impl<P: Participant> Handler<Subscribe> for ParticipantActor<P> {
+    type Result = MessageResult<Subscribe>;
+
+    fn handle(&mut self, msg: Subscribe, _ctx: &mut Context<Self>) -> Self::Result {
+        let end = self.replication_start + self.replication_log.len() as u64;
+        let (sender, receiver) = unbounded_channel();
+        let resumes = msg.epoch == self.epoch && (self.replication_start..=end).contains(&msg.from);
+        let (snapshot, from) = if resumes {
+            println!(
+                "[{}] Standby resumed from entry {} of {}",
+                P::NAME,
+                msg.from,
+                end
+            );
+            let skipped = (msg.from - self.replication_start) as usize;
+            for entry in self.replication_log.iter().skip(skipped) {
+                let _ = sender.send(entry.clone());
+            }
+            (0, msg.from)
+        } else {
+            let snapshot = self.snapshot();
+            println!(
+                "[{}] Standby resyncing from a snapshot of {} transactions",
+                P::NAME,
+                self.transaction_log.len()
+            );
+            for entry in &snapshot {
+                let _ = sender.send(entry.clone());
+            }
+            (snapshot.len(), end)
+        };
+        self.replicas.push(sender);
+        MessageResult(Subscription {
+            epoch: self.epoch,
+            snapshot,
+            from,
+            log: receiver,
+        })
+    }
+}
+
+impl<P: Participant> Handler<Acknowledge> for ParticipantActor<P> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Acknowledge, _ctx: &mut Context<Self>) -> Self::Result {
+        while self.replication_start < msg.upto && self.replication_log.pop_front().is_some() {
+            self.replication_start += 1;
+        }
+    }
+}
+
+impl<P: Participant> Handler<Promote> for ParticipantActor<P> {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Promote, ctx: &mut Context<Self>) -> Self::Result {
+        self.standby = false;
+        // Positions of the log of the previous primary mean nothing here.
+        self.epoch = now_ms();
+        self.replication_log.clear();
+        self.replication_start = 0;
+        println!("[{}] Promoted to primary, epoch {}", P::NAME, self.epoch);
+        let pending: Vec<u32> = self
+            .transaction_log
+            .iter()
+            .filter(|(_, state)| {
+                matches!(
+                    state,
+                    TransactionState::Prepared { .. } | TransactionState::CancelPrepared { .. }
+                )
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for transaction_id in pending {
+            // The time spent prepared on the old primary is not known here,
+            // so pending transactions get a whole new TTL.
+            self.prepared_at.insert(transaction_id, Instant::now());
+            self.schedule_expiry(transaction_id, ctx);
+        }
+    }
+}
+
+impl<P: Participant> Handler<AdminCommand> for ParticipantActor<P> {
+    type Result = String;
+
+    fn handle(&mut self, msg: AdminCommand, _ctx: &mut Context<Self>) -> Self::Result {
+        println!("[{}] admin: {:?}", P::NAME, msg.words);
+        let words: Vec<&str> = msg.words.iter().map(String::as_str).collect();
+        match words.as_slice() {
+            ["list"] => self.list(None),
+            ["list", state] => self.list(Some(state)),
+            ["show", id] => match id.parse().ok().and_then(|id| {
+                let state = self.transaction_log.get(&id)?;
+                Some(Self::describe(id, state))
+            }) {
+                Some(description) => description + "\n",
+                None => format!("unknown transaction {}\n", id),
+            },
+            ["inventory"] => self.participant.inventory() + "\n",
+            ["counters"] => format!("{:?}\n", self.counters),
+            ["audit"] => self
+                .audit_trail
+                .iter()
+                .map(|entry| format!("{}\n", entry))
+                .collect(),
+            words => match self.participant.admin(words) {
+                Some(response) => response + "\n",
+                None => format!("{}\n", ADMIN_HELP),
+            },
+        }
+    }
+}
+
+impl<P: Participant> Handler<Shutdown> for ParticipantActor<P> {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Context<Self>) -> Self::Result {
+        let pending = self
+            .transaction_log
+            .values()
+            .filter(|state| matches!(state, TransactionState::Prepared { .. }))
+            .count();
+        println!(
+            "[{}] Stopping with {} prepared transactions pending",
+            P::NAME,
+            pending
+        );
+        self.participant.flush();
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Money;
+
+    #[derive(Default)]
+    struct Counter {
+        prepared: u32,
+        committed: u32,
+        aborted: u32,
+    }
+
+    impl Participant for Counter {
+        const NAME: &'static str = "COUNTER";
+        const ENTITY: u8 = 0;
+
+        fn on_prepare(&mut self, transaction: &AlgloboTransaction) -> bool {
+            self.prepared += 1;
+            transaction.client != "falla"
+        }
+
+        fn on_commit(&mut self, _transaction: &AlgloboTransaction, _timestamp_ms: u64) {
+            self.committed += 1;
+        }
+
+        fn on_abort(&mut self, _transaction: &AlgloboTransaction) {
+            self.aborted += 1;
+        }
+
+        fn on_prepare_cancellation(&mut self, transaction: &AlgloboTransaction) -> bool {
+            transaction.client != "falla_cancelacion"
+        }
+
+        fn on_cancel(&mut self, _transaction: &AlgloboTransaction, _timestamp_ms: u64) {
+            self.committed -= 1;
+        }
+
+        fn inventory(&self) -> String {
+            format!("committed={}", self.committed)
+        }
+    }
+
+    fn transaction(id: u32, client: &str) -> AlgloboTransaction {
+        AlgloboTransaction {
+            id,
+            client: client.into(),
+            hotel_price: Money::from_minor_units(10),
+            hotel_currency: "USD".parse().unwrap(),
+            airline_price: Money::from_minor_units(20),
+            airline_currency: "USD".parse().unwrap(),
+        }
+    }
+
+    const OK: TransactionMessage = TransactionMessage::Response { success: true };
+    const FAILURE: TransactionMessage = TransactionMessage::Response { success: false };
+
+    #[test]
+    fn test_state_machine_is_idempotent() {
+        let mut actor = ParticipantActor::new(Counter::default());
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        actor.commit(1, 0);
+        actor.commit(1, 0);
+        actor.abort(1);
+
+        assert_eq!(actor.prepare(transaction(2, "falla")), FAILURE);
+        assert_eq!(actor.prepare(transaction(3, "viole")), OK);
+        actor.abort(3);
+        actor.abort(3);
+        actor.commit(3, 0);
+
+        assert_eq!(actor.participant.prepared, 3);
+        assert_eq!(actor.participant.committed, 1);
+        assert_eq!(actor.participant.aborted, 1);
+    }
+
+    #[test]
+    fn test_expiry_presumes_abort() {
+        let mut actor = ParticipantActor::new(Counter::default());
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        assert_eq!(actor.prepare(transaction(2, "viole")), OK);
+        actor.commit(2, 0);
+
+        let first = actor.arm_expiry(1);
+        let second = actor.arm_expiry(2);
+        actor.expire(1, first);
+        actor.expire(2, second);
+        // A decision arriving after the expiry has no effect.
+        actor.commit(1, 0);
+
+        assert_eq!(actor.participant.committed, 1);
+        assert_eq!(actor.participant.aborted, 1);
+    }
+
+    #[test]
+    fn test_stale_expiry_keeps_cancellation() {
+        let mut actor = ParticipantActor::new(Counter::default());
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        let prepare_timer = actor.arm_expiry(1);
+        assert_eq!(actor.commit(1, 0), OK);
+        assert_eq!(actor.prepare_cancellation(1), OK);
+        let cancellation_timer = actor.arm_expiry(1);
+
+        // The timer of the prepare fires while the cancellation is pending.
+        actor.expire(1, prepare_timer);
+        assert!(matches!(
+            actor.transaction_log[&1],
+            TransactionState::CancelPrepared { .. }
+        ));
+        actor.expire(1, cancellation_timer);
+        assert!(matches!(
+            actor.transaction_log[&1],
+            TransactionState::Committed { .. }
+        ));
+        assert_eq!(actor.counters.expired, 1);
+    }
+
+    #[test]
+    fn test_conflicting_prepare_is_rejected() {
+        let mut actor = ParticipantActor::new(Counter::default());
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        assert!(matches!(
+            actor.prepare(transaction(1, "viole")),
+            TransactionMessage::Rejected { .. }
+        ));
+
+        let mut other_prices = transaction(1, "lucho");
+        other_prices.hotel_price = Money::from_minor_units(11);
+        assert!(matches!(
+            actor.prepare(other_prices),
+            TransactionMessage::Rejected { .. }
+        ));
+
+        actor.commit(1, 0);
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        assert_eq!(actor.participant.prepared, 1);
+    }
+
+    #[test]
+    fn test_prepare_after_abort_is_rejected() {
+        let mut actor = ParticipantActor::new(Counter::default());
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        assert_eq!(actor.abort(1), OK);
+        assert!(matches!(
+            actor.prepare(transaction(1, "lucho")),
+            TransactionMessage::Rejected { .. }
+        ));
+
+        assert_eq!(actor.prepare(transaction(2, "viole")), OK);
+        assert_eq!(actor.commit(2, 0), OK);
+        assert_eq!(actor.prepare_cancellation(2), OK);
+        assert_eq!(actor.commit_cancellation(2, 0), OK);
+        assert!(matches!(
+            actor.prepare(transaction(2, "viole")),
+            TransactionMessage::Rejected { .. }
+        ));
+        assert_eq!(actor.participant.prepared, 2);
+    }
+
+    #[test]
+    fn test_prepare_after_expiry_is_rejected() {
+        let mut actor = ParticipantActor::new(Counter::default());
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        let generation = actor.arm_expiry(1);
+        actor.expire(1, generation);
+        // A new coordinator retrying the payment must not get a yes.
+        assert!(matches!(
+            actor.prepare(transaction(1, "lucho")),
+            TransactionMessage::Rejected { .. }
+        ));
+        assert!(matches!(
+            actor.commit(1, 0),
+            TransactionMessage::Rejected { .. }
+        ));
+        assert_eq!(actor.participant.prepared, 1);
+        assert_eq!(actor.participant.aborted, 1);
+        assert_eq!(actor.participant.committed, 0);
+    }
+
+    #[test]
+    fn test_decisions_on_unknown_transactions() {
+        let mut actor = ParticipantActor::new(Counter::default());
+        assert_eq!(
+            actor.commit(7, 0),
+            TransactionMessage::UnknownTransaction { transaction_id: 7 }
+        );
+        assert_eq!(
+            actor.abort(8),
+            TransactionMessage::UnknownTransaction { transaction_id: 8 }
+        );
+        assert!(matches!(
+            actor.prepare(transaction(8, "lucho")),
+            TransactionMessage::Rejected { .. }
+        ));
+        assert_eq!(actor.counters.unknown, 2);
+        assert_eq!(actor.participant.committed, 0);
+    }
+
+    #[test]
+    fn test_illegal_transitions_are_rejected() {
+        let mut actor = ParticipantActor::new(Counter::default());
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        assert_eq!(actor.commit(1, 0), OK);
+        assert!(matches!(
+            actor.abort(1),
+            TransactionMessage::Rejected { .. }
+        ));
+
+        assert_eq!(actor.prepare(transaction(2, "viole")), OK);
+        assert_eq!(actor.abort(2), OK);
+        assert_eq!(actor.abort(2), OK);
+        assert!(matches!(
+            actor.commit(2, 0),
+            TransactionMessage::Rejected { .. }
+        ));
+
+        assert_eq!(actor.participant.committed, 1);
+        assert_eq!(actor.participant.aborted, 1);
+        assert_eq!(actor.audit_trail.len(), 2);
+        assert!(matches!(
+            actor.transaction_log[&1],
+            TransactionState::Committed { .. }
+        ));
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let mut actor = ParticipantActor::new(Counter::default());
+        assert_eq!(actor.prepare(transaction(1, "lucho")), OK);
+        assert!(matches!(
+            actor.prepare_cancellation(1),
+            TransactionMessage::Rejected { .. }
+        ));
+        assert_eq!(actor.commit(1, 0), OK);
+        assert_eq!(actor.prepare_cancellation(1), OK);
+        assert_eq!(actor.prepare_cancellation(1), OK);
+        assert_eq!(actor.commit_cancellation(1, 0), OK);
+        assert_eq!(actor.commit_cancellation(1, 0), OK);
+        assert!(matches!(
+            actor.abort_cancellation(1),
+            TransactionMessage::Rejected { .. }
+        ));
+
+        // An aborted cancellation leaves the transaction committed.
+        assert_eq!(actor.prepare(transaction(2, "viole")), OK);
+        assert_eq!(actor.commit(2, 0), OK);
+        assert_eq!(actor.prepare_cancellation(2), OK);
+        assert_eq!(actor.abort_cancellation(2), OK);
+        assert_eq!(actor.prepare(transaction(3, "falla_cancelacion")), OK);
+        assert_eq!(actor.commit(3, 0), OK);
+        assert_eq!(actor.prepare_cancellation(3), FAILURE);
+
+        assert_eq!(
+            actor.prepare_cancellation(4),
+            TransactionMessage::UnknownTransaction { transaction_id: 4 }
+        );
+        assert_eq!(actor.participant.committed, 2);
+        assert!(matches!(
+            actor.transaction_log[&1],
+            TransactionState::Cancelled { .. }
+        ));
+        assert!(matches!(
+            actor.transaction_log[&2],
+            TransactionState::Committed { .. }
+        ));
+    }
+}
@@ -5,6 +5,16 @@ use tokio::net::TcpStream;
 
 use std::io::Result;
 
+/// What a participant answered to a commit or an abort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecisionOutcome {
+    Applied,
+    /// The participant has no record of the transaction.
+    UnknownTransaction,
+    /// The participant refused the decision or did not answer.
+    Failed,
+}
+
 pub struct Protocol {
     stream: TcpStream,
 }
@@ -13,10 +23,10 @@ impl Protocol {
     pub fn new(stream: TcpStream) -> Self {
         Self { stream }
     }
-    pub async fn commit(&mut self, transaction_id: u32) -> Result<bool> {
+    pub async fn commit(&mut self, transaction_id: u32) -> Result<DecisionOutcome> {
         self.send(TransactionMessage::Commit { transaction_id })
             .await?;
-        Ok(self.read_response().await)
+        Ok(self.read_decision_response().await)
     }
 
     pub async fn prepare(&mut self, transaction: &AlgloboTransaction) -> Result<bool> {
@@ -27,22 +37,84 @@ impl Protocol {
         Ok(self.read_response().await)
     }
 
+    pub async fn prepare_cancellation(&mut self, transaction_id: u32) -> Result<bool> {
+        self.send(TransactionMessage::PrepareCancellation { transaction_id })
+            .await?;
+        Ok(self.read_response().await)
+    }
+
+    pub async fn commit_cancellation(&mut self, transaction_id: u32) -> Result<DecisionOutcome> {
+        self.send(TransactionMessage::CommitCancellation { transaction_id })
+            .await?;
+        Ok(self.read_decision_response().await)
+    }
+
+    pub async fn abort_cancellation(&mut self, transaction_id: u32) -> Result<DecisionOutcome> {
+        self.send(TransactionMessage::AbortCancellation { transaction_id })
+            .await?;
+        Ok(self.read_decision_response().await)
+    }
+
     async fn read_response(&mut self) -> bool {
         match self.receive().await {
-            Some(TransactionMessage::Response { success }) => success,
+            Some(Ok(TransactionMessage::Response { success })) => success,
+            Some(Ok(TransactionMessage::Rejected { reason })) => {
+                println!("Request rejected: {}", reason);
+                false
+            }
+            Some(Ok(TransactionMessage::UnknownTransaction { transaction_id })) => {
+                println!("Transaction {} is unknown", transaction_id);
+                false
+            }
+            Some(Err(e)) => {
+                println!("Invalid response: {}", e);
+                false
+            }
             None => false,
             res => panic!("Invalid prepare response: {:?}", res),
         }
     }
 
-    pub async fn abort(&mut self, transaction_id: u32) -> Result<bool> {
+    pub async fn abort(&mut self, transaction_id: u32) -> Result<DecisionOutcome> {
         self.send(TransactionMessage::Abort { transaction_id })
             .await?;
-        Ok(self.read_response().await)
+        Ok(self.read_decision_response().await)
     }
 
-    async fn send(&mut self, msg: TransactionMessage) -> Result<()> {
-        let payload = msg.to_bytes();
+    async fn read_decision_response(&mut self) -> DecisionOutcome {
+        match self.receive().await {
+            Some(Ok(TransactionMessage::Response { success: true })) => DecisionOutcome::Applied,
+            Some(Ok(TransactionMessage::UnknownTransaction { .. })) => {
+                DecisionOutcome::UnknownTransaction
+            }
+            Some(Ok(TransactionMessage::Rejected { reason })) => {
+                println!("Request rejected: {}", reason);
+                DecisionOutcome::Failed
+            }
+            Some(Err(e)) => {
+                println!("Invalid response: {}", e);
+                DecisionOutcome::Failed
+            }
+            Some(Ok(TransactionMessage::Response { success: false })) | None => {
+                DecisionOutcome::Failed
+            }
+            res => panic!("Invalid decision response: {:?}", res),
+        }
+    }
+
+    pub async fn send(&mut self, msg: TransactionMessage) -> Result<()> {
+        self.send_frame(msg.to_bytes()).await
+    }
+
+    /// The next message, `None` once the connection is closed or an error if
+    /// the frame is not a valid message.
+    pub async fn receive(&mut self) -> Option<Result<TransactionMessage>> {
+        let buf = self.receive_frame().await?;
+        Some(TransactionMessage::from_bytes(&buf))
+    }
+
+    /// Sends `payload` prefixed by its length, like every message.
+    pub async fn send_frame(&mut self, payload: Vec<u8>) -> Result<()> {
         let sz = payload.len() as u32;
         let mut full_payload = Vec::new();
         full_payload.extend_from_slice(&sz.to_le_bytes());
@@ -50,19 +122,12 @@ impl Protocol {
         self.stream.write_all(&full_payload).await
     }
 
-    pub async fn receive(&mut self) -> Option<TransactionMessage> {
+    pub async fn receive_frame(&mut self) -> Option<Vec<u8>> {
         let mut sz = [0u8; 4];
-        if self.stream.read_exact(&mut sz).await.is_ok() {
-            let mut buf = vec![0u8; u32::from_le_bytes(sz) as usize];
-            if self.stream.read_exact(&mut buf).await.is_ok() {
-                let message = TransactionMessage::from_bytes(&buf);
-                Some(message)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+        self.stream.read_exact(&mut sz).await.ok()?;
+        let mut buf = vec![0u8; u32::from_le_bytes(sz) as usize];
+        self.stream.read_exact(&mut buf).await.ok()?;
+        Some(buf)
     }
     pub async fn send_ok(&mut self) -> Result<()> {
         self.send(TransactionMessage::Response { success: true })
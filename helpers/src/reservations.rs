@@ -0,0 +1,118 @@
+use std::fmt;
+
+/// Reservations kept by the hotel and the airline, counting the ones whose
+/// commit or cancellation is still pending so every decision can be applied.
+#[derive(Debug, Default)]
+pub struct Reservations {
+    committed: u32,
+    /// Reservations prepared but not decided yet.
+    pending: u32,
+    /// Reservations whose cancellation was prepared but not decided yet.
+    cancelling: u32,
+}
+
+impl Reservations {
+    /// Refuses reservations that could not be counted once every pending one
+    /// is committed, so commits can always be applied.
+    pub fn prepare(&mut self) -> bool {
+        let pending = self
+            .pending
+            .checked_add(1)
+            .filter(|pending| self.committed.checked_add(*pending).is_some());
+        match pending {
+            Some(pending) => {
+                self.pending = pending;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Counts a prepared reservation, returning how many there are now.
+    pub fn commit(&mut self) -> u32 {
+        // Both were checked when the reservation was prepared.
+        self.pending = self
+            .pending
+            .checked_sub(1)
+            .expect("Committed a reservation that was not prepared");
+        self.committed = self
+            .committed
+            .checked_add(1)
+            .expect("Reservations overflow");
+        self.committed
+    }
+
+    pub fn abort(&mut self) {
+        self.pending = self
+            .pending
+            .checked_sub(1)
+            .expect("Aborted a reservation that was not prepared");
+    }
+
+    /// Refuses to cancel more reservations than there are left to cancel.
+    pub fn prepare_cancellation(&mut self) -> bool {
+        if self.cancelling >= self.committed {
+            return false;
+        }
+        // Below the reservations, so it cannot overflow.
+        self.cancelling = self
+            .cancelling
+            .checked_add(1)
+            .expect("Cancellations overflow");
+        true
+    }
+
+    pub fn cancel(&mut self) {
+        // Both were checked when the cancellation was prepared.
+        self.cancelling = self
+            .cancelling
+            .checked_sub(1)
+            .expect("Cancelled a reservation that was not being cancelled");
+        self.committed = self
+            .committed
+            .checked_sub(1)
+            .expect("Cancelled more reservations than were committed");
+    }
+
+    pub fn abort_cancellation(&mut self) {
+        self.cancelling = self
+            .cancelling
+            .checked_sub(1)
+            .expect("Kept a reservation that was not being cancelled");
+    }
+}
+
+impl fmt::Display for Reservations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "reservations={} pending={}",
+            self.committed, self.pending
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decisions_can_always_be_applied() {
+        let mut reservations = Reservations {
+            committed: u32::MAX - 1,
+            ..Default::default()
+        };
+        assert!(reservations.prepare());
+        assert!(!reservations.prepare());
+        assert_eq!(reservations.commit(), u32::MAX);
+
+        let mut reservations = Reservations::default();
+        assert!(!reservations.prepare_cancellation());
+        assert!(reservations.prepare());
+        reservations.commit();
+        assert!(reservations.prepare_cancellation());
+        assert!(!reservations.prepare_cancellation());
+        reservations.cancel();
+        assert_eq!(reservations.to_string(), "reservations=0 pending=0");
+    }
+}
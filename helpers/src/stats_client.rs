@@ -0,0 +1,241 @@
+use crate::event::Event;
+use crate::event_protocol::EventProtocol;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+
+/// Events kept while stats is unreachable, the oldest are dropped beyond it.
+pub const STATS_BUFFER_CAPACITY: usize = 10_000;
+/// How long to wait on shutdown for the pending events to reach stats.
+pub const STATS_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Buffer {
+    events: VecDeque<Event>,
+    capacity: usize,
+    /// Events dropped because the buffer was full, since last reported.
+    dropped: u64,
+    /// Whether an event taken from the buffer is still being sent.
+    in_flight: bool,
+}
+
+impl Buffer {
+    fn push_back(&mut self, event: Event) {
+        if self.events.len() >= self.capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+
+    /// Puts back an event that could not be sent, unless newer ones already
+    /// filled the buffer, as it is the oldest one.
+    fn requeue(&mut self, event: Event) {
+        if self.events.len() >= self.capacity {
+            self.dropped += 1;
+        } else {
+            self.events.push_front(event);
+        }
+        self.in_flight = false;
+    }
+}
+
+struct Shared {
+    buffer: Mutex<Buffer>,
+    wakeup: Notify,
+}
+
+impl Shared {
+    fn buffer(&self) -> std::sync::MutexGuard<'_, Buffer> {
+        self.buffer.lock().expect("Stats buffer poisoned")
+    }
+}
+
+/// Reports events to stats without ever blocking or failing the caller.
+///
+/// Events go into a bounded buffer that a background task sends to stats.
+/// The task connects when the first event arrives and reconnects whenever
+/// the connection is lost, keeping the events meanwhile. Once the buffer is
+/// full the oldest events are dropped.
+///
+/// Clones share the same buffer and background task.
+#[derive(Clone)]
+pub struct StatsClient {
+    shared: Arc<Shared>,
+}
+
+impl StatsClient {
+    /// Must be called within a runtime, where the background task runs.
+    pub fn new(host: &str, capacity: usize) -> Self {
+        let shared = Arc::new(Shared {
+            buffer: Mutex::new(Buffer {
+                events: VecDeque::new(),
+                capacity: capacity.max(1),
+                dropped: 0,
+                in_flight: false,
+            }),
+            wakeup: Notify::new(),
+        });
+        actix_rt::spawn(send_events(host.to_string(), shared.clone()));
+        Self { shared }
+    }
+
+    pub fn send(&self, event: Event) {
+        self.shared.buffer().push_back(event);
+        self.shared.wakeup.notify_one();
+    }
+
+    /// Waits up to `timeout` for every buffered event to be sent. Returns
+    /// whether they all were.
+    pub async fn flush(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            {
+                let buffer = self.shared.buffer();
+                if buffer.events.is_empty() && !buffer.in_flight {
+                    return true;
+                }
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            actix_rt::time::sleep(FLUSH_POLL_INTERVAL).await;
+        }
+    }
+}
+
+async fn send_events(host: String, shared: Arc<Shared>) {
+    let mut connection: Option<EventProtocol> = None;
+    loop {
+        let next = {
+            let mut buffer = shared.buffer();
+            let event = buffer.events.pop_front();
+            buffer.in_flight = event.is_some();
+            event
+        };
+        let event = match next {
+            Some(event) => event,
+            None => {
+                shared.wakeup.notified().await;
+                continue;
+            }
+        };
+
+        let protocol = match &mut connection {
+            Some(protocol) => protocol,
+            None => match TcpStream::connect(&host).await {
+                Ok(stream) => {
+                    println!("Connected to stats at {}", host);
+                    connection.insert(EventProtocol::new(stream))
+                }
+                Err(e) => {
+                    println!("Could not connect to stats at {}: {}", host, e);
+                    shared.buffer().requeue(event);
+                    actix_rt::time::sleep(RECONNECT_INTERVAL).await;
+                    continue;
+                }
+            },
+        };
+        match protocol.send_event(event.clone()).await {
+            Ok(()) => {
+                let mut buffer = shared.buffer();
+                buffer.in_flight = false;
+                if buffer.dropped > 0 {
+                    println!(
+                        "Dropped {} events while stats was unreachable",
+                        buffer.dropped
+                    );
+                    buffer.dropped = 0;
+                }
+            }
+            Err(e) => {
+                println!("Lost connection with stats at {}: {}", host, e);
+                connection = None;
+                shared.buffer().requeue(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{EventKind, EventSource};
+
+    fn event(transaction_id: u32) -> Event {
+        Event::new(
+            EventSource::Replica(30),
+            transaction_id,
+            EventKind::PaymentStarted,
+        )
+    }
+
+    fn buffer(capacity: usize) -> Buffer {
+        Buffer {
+            events: VecDeque::new(),
+            capacity,
+            dropped: 0,
+            in_flight: false,
+        }
+    }
+
+    fn ids(buffer: &Buffer) -> Vec<u32> {
+        buffer
+            .events
+            .iter()
+            .map(|event| event.transaction_id)
+            .collect()
+    }
+
+    #[test]
+    fn test_full_buffer_drops_the_oldest() {
+        let mut buffer = buffer(2);
+        for id in 1..=4 {
+            buffer.push_back(event(id));
+        }
+        assert_eq!(ids(&buffer), vec![3, 4]);
+        assert_eq!(buffer.dropped, 2);
+    }
+
+    #[test]
+    fn test_requeue() {
+        let mut buffer = buffer(2);
+        buffer.push_back(event(1));
+        buffer.push_back(event(2));
+
+        // Sending 1 failed while there was room for it again.
+        let first = buffer.events.pop_front().unwrap();
+        buffer.in_flight = true;
+        buffer.requeue(first);
+        assert_eq!(ids(&buffer), vec![1, 2]);
+        assert_eq!(buffer.dropped, 0);
+        assert!(!buffer.in_flight);
+
+        // Sending 1 failed after 3 took its place, so it is the one dropped.
+        let first = buffer.events.pop_front().unwrap();
+        buffer.in_flight = true;
+        buffer.push_back(event(3));
+        buffer.requeue(first);
+        assert_eq!(ids(&buffer), vec![2, 3]);
+        assert_eq!(buffer.dropped, 1);
+        assert!(!buffer.in_flight);
+    }
+
+    #[actix_rt::test]
+    async fn test_flush_times_out_while_stats_is_unreachable() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        // Nobody listens there anymore.
+        drop(listener);
+
+        let client = StatsClient::new(&host, 10);
+        assert!(client.flush(Duration::from_millis(50)).await);
+        client.send(event(1));
+        assert!(!client.flush(Duration::from_millis(200)).await);
+        assert_eq!(client.shared.buffer().events.len(), 1);
+    }
+}
@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// First byte of a query frame on the stats listener. It is not the opcode
+/// of any `Event`, so queries and events share the same connections.
+pub const QUERY_OPCODE: u8 = b'?';
+
+/// Where the stats clients look for stats unless `--stats-host` says otherwise.
+pub const DEFAULT_STATS_HOST: &str = "127.0.0.1:9996";
+
+/// Latency percentiles of a histogram, in milliseconds. They are `None`
+/// when there are no samples.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Percentiles {
+    pub samples: u64,
+    pub p50: Option<u64>,
+    pub p90: Option<u64>,
+    pub p95: Option<u64>,
+    pub p99: Option<u64>,
+    pub max: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaymentsReport {
+    pub succeeded: u64,
+    pub failed: u64,
+    pub average_ms: Option<u64>,
+    pub latency: Percentiles,
+    pub started: u64,
+    pub prepared: u64,
+    pub committed: u64,
+    pub aborted: u64,
+    /// Payments started but neither committed nor aborted yet.
+    pub in_flight: u64,
+}
+
+/// Payments over one of the rolling windows, such as `1m`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowReport {
+    pub window: String,
+    pub payments_per_second: f64,
+    /// Share of the payments that failed, from 0 to 1.
+    pub failure_rate: f64,
+    pub latency: Percentiles,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseReport {
+    pub phase: String,
+    pub latency: Percentiles,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityReport {
+    pub entity: String,
+    pub committed: u64,
+    pub failed: u64,
+    pub average_ms: Option<u64>,
+    /// Payments that failed because this entity rejected them.
+    pub payments_rejected: u64,
+    /// Time transactions spent prepared until they were committed.
+    pub prepared: Percentiles,
+    pub phases: Vec<PhaseReport>,
+}
+
+/// Round trip of a phase with an entity, as seen by AlGlobo.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub entity: String,
+    pub phase: String,
+    pub duration_ms: u32,
+}
+
+/// One of the slowest successful payments.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlowPayment {
+    pub transaction_id: u32,
+    /// The AlGlobo replica that processed it.
+    pub source: String,
+    /// When it finished, in milliseconds since the unix epoch.
+    pub finished_at_ms: u64,
+    pub duration_ms: u32,
+    pub phases: Vec<PhaseTiming>,
+}
+
+/// A single failure, as reported by an entity or AlGlobo.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecentFailure {
+    pub transaction_id: u32,
+    pub source: String,
+    pub reason: String,
+    /// When it happened, in milliseconds since the unix epoch.
+    pub at_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailureReport {
+    pub source: String,
+    pub reason: String,
+    pub count: u64,
+}
+
+/// The current aggregates of stats, as answered to a query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsReport {
+    pub payments: PaymentsReport,
+    pub windows: Vec<WindowReport>,
+    pub entities: Vec<EntityReport>,
+    /// Failures since startup by cause, most frequent first.
+    pub failures: Vec<FailureReport>,
+    /// The last failures, most recent first.
+    pub recent_failures: Vec<RecentFailure>,
+    /// Id of the AlGlobo replica that last reported being the leader.
+    pub leader: Option<u8>,
+    pub leader_changes: u64,
+    /// Slowest first.
+    pub slowest: Vec<SlowPayment>,
+}
+
+impl StatsReport {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Stats report is always serializable")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// A value in milliseconds, or `-` when there were no samples.
+pub fn format_ms(value: Option<u64>) -> String {
+    value.map_or_else(|| "-".into(), |value| value.to_string())
+}
+
+impl fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.samples == 0 {
+            return write!(f, "no samples");
+        }
+        write!(
+            f,
+            "p50={} p90={} p95={} p99={} max={} milisecs ({} samples)",
+            format_ms(self.p50),
+            format_ms(self.p90),
+            format_ms(self.p95),
+            format_ms(self.p99),
+            format_ms(self.max),
+            self.samples
+        )
+    }
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let payments = &self.payments;
+        writeln!(
+            f,
+            "Payments : {} succeeded, {} failed, average : {} milisecs",
+            payments.succeeded,
+            payments.failed,
+            format_ms(payments.average_ms)
+        )?;
+        writeln!(
+            f,
+            "    started : {}, prepared : {}, committed : {}, aborted : {}, in flight : {}",
+            payments.started,
+            payments.prepared,
+            payments.committed,
+            payments.aborted,
+            payments.in_flight
+        )?;
+        writeln!(f, "    latency : {}", payments.latency)?;
+        for window in &self.windows {
+            writeln!(
+                f,
+                "    last {} : {:.2} payments/s, failure rate : {:.1}%, latency : {}",
+                window.window,
+                window.payments_per_second,
+                100.0 * window.failure_rate,
+                window.latency
+            )?;
+        }
+        for entity in &self.entities {
+            writeln!(
+                f,
+                "Entity : {} {} committed, {} failed, average : {} milisecs, rejected {} payments",
+                entity.entity,
+                entity.committed,
+                entity.failed,
+                format_ms(entity.average_ms),
+                entity.payments_rejected
+            )?;
+            writeln!(f, "    prepared until commit : {}", entity.prepared)?;
+            for phase in &entity.phases {
+                writeln!(f, "    {} : {}", phase.phase, phase.latency)?;
+            }
+        }
+        for failure in &self.failures {
+            writeln!(
+                f,
+                "Failures : {} {} {}",
+                failure.source, failure.reason, failure.count
+            )?;
+        }
+        for failure in &self.recent_failures {
+            writeln!(
+                f,
+                "Recent failure : transaction {} {} {} at {}",
+                failure.transaction_id, failure.source, failure.reason, failure.at_ms
+            )?;
+        }
+        for payment in &self.slowest {
+            let phases: Vec<String> = payment
+                .phases
+                .iter()
+                .map(|phase| format!("{} {} {}", phase.entity, phase.phase, phase.duration_ms))
+                .collect();
+            writeln!(
+                f,
+                "Slow payment : {} took {} milisecs, by {} at {} ({})",
+                payment.transaction_id,
+                payment.duration_ms,
+                payment.source,
+                payment.finished_at_ms,
+                phases.join(", ")
+            )?;
+        }
+        match self.leader {
+            Some(leader) => writeln!(
+                f,
+                "Leader : AlGlobo replica {} ({} leader changes)",
+                leader, self.leader_changes
+            ),
+            None => writeln!(f, "Leader : unknown"),
+        }
+    }
+}
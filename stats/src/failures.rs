@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Where a failure happened and why.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Cause {
+    /// The entity that failed, or `payment` for failed payments.
+    pub source: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.source, self.reason)
+    }
+}
+
+#[derive(Default)]
+struct CauseCount {
+    total: u64,
+    /// When each failure within the window happened, oldest first.
+    recent: VecDeque<Instant>,
+}
+
+/// Failures counted by cause, since startup and within a sliding window, so
+/// a spike of a single cause stands out from the overall totals.
+pub struct FailureBreakdown {
+    window: Duration,
+    causes: HashMap<Cause, CauseCount>,
+}
+
+impl FailureBreakdown {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            causes: HashMap::new(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn record(&mut self, source: &str, reason: &str, at: Instant) {
+        let cause = Cause {
+            source: source.into(),
+            reason: reason.into(),
+        };
+        let count = self.causes.entry(cause).or_default();
+        count.total = count.total.saturating_add(1);
+        count.recent.push_back(at);
+
+        // Forget what fell out of the window, so memory does not grow with
+        // the total number of failures.
+        let window = self.window;
+        for count in self.causes.values_mut() {
+            while matches!(count.recent.front(), Some(t) if at.duration_since(*t) > window) {
+                count.recent.pop_front();
+            }
+        }
+    }
+
+    /// Counts a failure that happened before stats started, so it is only
+    /// part of the totals and not of the window.
+    pub fn record_past(&mut self, source: &str, reason: &str) {
+        let cause = Cause {
+            source: source.into(),
+            reason: reason.into(),
+        };
+        let count = self.causes.entry(cause).or_default();
+        count.total = count.total.saturating_add(1);
+    }
+
+    /// Restores the totals saved from `totals`. Failures within the window
+    /// are not saved, so the window starts empty.
+    pub fn restore(&mut self, totals: Vec<(Cause, u64)>) {
+        for (cause, total) in totals {
+            self.causes.entry(cause).or_default().total = total;
+        }
+    }
+
+    /// Every cause with its total count, sorted by cause.
+    pub fn totals(&self) -> Vec<(&Cause, u64)> {
+        let mut totals: Vec<_> = self
+            .causes
+            .iter()
+            .map(|(cause, count)| (cause, count.total))
+            .collect();
+        totals.sort();
+        totals
+    }
+
+    /// The `n` causes with most failures since startup.
+    pub fn top(&self, n: usize) -> Vec<(&Cause, u64)> {
+        Self::top_of(self.totals(), n)
+    }
+
+    /// The `n` causes with most failures within the window ending at `now`.
+    pub fn top_recent(&self, n: usize, now: Instant) -> Vec<(&Cause, u64)> {
+        let counts = self
+            .causes
+            .iter()
+            .map(|(cause, count)| {
+                let expired = count
+                    .recent
+                    .partition_point(|t| now.duration_since(*t) > self.window);
+                (cause, (count.recent.len() - expired) as u64)
+            })
+            .collect();
+        Self::top_of(counts, n)
+    }
+
+    fn top_of(mut counts: Vec<(&Cause, u64)>, n: usize) -> Vec<(&Cause, u64)> {
+        counts.retain(|(_, count)| *count > 0);
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        counts.truncate(n);
+        counts
+    }
+}
+
+/// Formats a list of causes as `bank prepare-rejected 3, hotel ... 1`.
+pub fn format_causes(causes: &[(&Cause, u64)]) -> String {
+    if causes.is_empty() {
+        return "none".into();
+    }
+    causes
+        .iter()
+        .map(|(cause, count)| format!("{} {}", cause, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_spike_stands_out() {
+        let mut failures = FailureBreakdown::new(Duration::from_secs(60));
+        let start = Instant::now();
+        for _ in 0..5 {
+            failures.record("hotel", "prepare-rejected", start);
+        }
+        let later = start + Duration::from_secs(120);
+        for _ in 0..2 {
+            failures.record("bank", "prepare-rejected", later);
+        }
+
+        let top = failures.top(1);
+        assert_eq!(top[0].0.source, "hotel");
+        assert_eq!(top[0].1, 5);
+        let recent = failures.top_recent(5, later);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].0.source, "bank");
+        assert_eq!(recent[0].1, 2);
+    }
+}
@@ -0,0 +1,152 @@
+use helpers::stats_query::Percentiles;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Percentiles reported for every histogram.
+pub const PERCENTILES: [f64; 4] = [50.0, 90.0, 95.0, 99.0];
+
+/// Latency histogram with fixed-width buckets of `resolution_ms`.
+///
+/// Only buckets with samples are stored, so there is no upper limit on the
+/// latencies it can hold. Percentiles are reported as the upper bound of the
+/// bucket they fall in, so they are accurate up to the resolution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Histogram {
+    resolution_ms: u64,
+    buckets: BTreeMap<u64, u64>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new(resolution_ms: u64) -> Self {
+        assert!(resolution_ms > 0, "Histogram resolution must be positive");
+        Self {
+            resolution_ms,
+            buckets: BTreeMap::new(),
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, value_ms: u64) {
+        *self
+            .buckets
+            .entry(value_ms / self.resolution_ms)
+            .or_default() += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value_ms);
+        self.max = self.max.max(value_ms);
+    }
+
+    /// Adds every sample of `other`, which must have the same resolution.
+    pub fn merge(&mut self, other: &Histogram) {
+        assert_eq!(
+            self.resolution_ms, other.resolution_ms,
+            "Can only merge histograms with the same resolution"
+        );
+        for (bucket, count) in &other.buckets {
+            *self.buckets.entry(*bucket).or_default() += count;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of every sample, saturated at `u64::MAX`.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// How many samples are known to be at or below `upper_bound_ms`, that is
+    /// the ones in buckets that end at or before it.
+    pub fn count_at_most(&self, upper_bound_ms: u64) -> u64 {
+        let end = upper_bound_ms.saturating_add(1) / self.resolution_ms;
+        self.buckets.range(..end).map(|(_, count)| count).sum()
+    }
+
+    /// Smallest latency that at least `percentile` percent of the samples
+    /// do not exceed, or `None` if there are no samples.
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                let upper_bound = (bucket + 1) * self.resolution_ms - 1;
+                return Some(upper_bound.min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// The reported `PERCENTILES` and the maximum, as answered to queries.
+    pub fn percentiles(&self) -> Percentiles {
+        Percentiles {
+            samples: self.count,
+            p50: self.percentile(50.0),
+            p90: self.percentile(90.0),
+            p95: self.percentile(95.0),
+            p99: self.percentile(99.0),
+            max: (self.count > 0).then_some(self.max),
+        }
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "no samples");
+        }
+        for percentile in PERCENTILES {
+            write!(
+                f,
+                "p{}={} ",
+                percentile,
+                self.percentile(percentile).unwrap_or(0)
+            )?;
+        }
+        write!(f, "max={} milisecs ({} samples)", self.max, self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = Histogram::new(10);
+        assert_eq!(histogram.percentile(50.0), None);
+        for value in 1..=100 {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.percentile(50.0), Some(59));
+        assert_eq!(histogram.percentile(99.0), Some(99));
+        assert_eq!(histogram.percentile(100.0), Some(100));
+        assert_eq!(histogram.count_at_most(5), 0);
+        assert_eq!(histogram.count_at_most(9), 9);
+        assert_eq!(histogram.count_at_most(25), 19);
+        assert_eq!(histogram.count_at_most(1000), 100);
+
+        // A single slow sample shows up in the tail but not in the median.
+        let mut histogram = Histogram::new(1);
+        for _ in 0..99 {
+            histogram.record(5);
+        }
+        histogram.record(3000);
+        assert_eq!(histogram.percentile(50.0), Some(5));
+        assert_eq!(histogram.percentile(99.0), Some(5));
+        assert_eq!(histogram.percentile(100.0), Some(3000));
+    }
+}
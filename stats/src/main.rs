@@ -1,67 +1,816 @@
-use std::sync::Arc;
+mod failures;
+mod histogram;
+mod metrics;
+mod persistence;
+mod slowest;
+mod timeseries;
+mod windows;
 
-use actix::{Actor, Context, Handler};
-use helpers::{event::Event, event_protocol::EventProtocol};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix::{Actor, AsyncContext, Context, Handler, Message, MessageResult};
+use failures::{format_causes, Cause, FailureBreakdown};
+use helpers::{
+    args::Args,
+    event::{entity_name, failure_name, now_ms, phase_name, Event, EventKind, PREPARE_EXPIRED},
+    event_protocol::{EventProtocol, StatsRequest},
+    shutdown::wait_for_shutdown_signal,
+    stats_query::{
+        EntityReport, FailureReport, PaymentsReport, PhaseReport, PhaseTiming, RecentFailure,
+        SlowPayment, StatsReport, WindowReport,
+    },
+};
+use histogram::{Histogram, PERCENTILES};
+use metrics::{serve_metrics, MetricsPage, RenderMetrics};
+use persistence::{load_snapshot, save_snapshot, EventLog};
+use serde::{Deserialize, Serialize};
+use slowest::SlowestPayments;
+use timeseries::TimeSeries;
+use windows::{SlidingWindows, WINDOWS};
 
 use tokio::net::TcpListener;
 
 extern crate actix;
 
+/// Adds `sample` to the running `total` of `count` samples. A sample that
+/// would overflow the total is rejected, leaving both untouched.
+fn add_sample(total: &mut u64, count: &mut u64, sample: u32) -> bool {
+    match (total.checked_add(sample as u64), count.checked_add(1)) {
+        (Some(new_total), Some(new_count)) => {
+            *total = new_total;
+            *count = new_count;
+            true
+        }
+        _ => {
+            println!(
+                "[STATS] Rejected sample of {} milisecs: total time overflow",
+                sample
+            );
+            false
+        }
+    }
+}
+
+const DEFAULT_BUCKET_MS: u64 = 10;
+const DEFAULT_METRICS_PORT: u16 = 9091;
+const DEFAULT_FAILURE_WINDOW_S: u64 = 60;
+/// How many failure causes are reported after each failure.
+const TOP_FAILURE_CAUSES: usize = 3;
+/// How many of the last failures are answered to queries.
+const RECENT_FAILURES: usize = 10;
+const DEFAULT_SNAPSHOT_PATH: &str = "./stats-snapshot.json";
+const DEFAULT_SNAPSHOT_INTERVAL_S: u64 = 30;
+const DEFAULT_WINDOW_REPORT_S: u64 = 10;
+const DEFAULT_SLOWEST: usize = 10;
+const DEFAULT_TIMESERIES_INTERVAL_S: u64 = 60;
+/// Bumped whenever the snapshot format changes in an incompatible way.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Outcomes reported by a single participant.
+#[derive(Clone, Serialize, Deserialize)]
+struct EntityStats {
+    tot_time: u64,
+    successes: u64,
+    failures: u64,
+    /// Time transactions spent prepared until they were committed.
+    prepared: Histogram,
+    /// Round trip of each phase with this entity, by phase.
+    phases: BTreeMap<u8, Histogram>,
+    /// Payments that failed because this entity rejected them.
+    #[serde(default)]
+    payments_rejected: u64,
+}
+
+impl EntityStats {
+    fn new(resolution_ms: u64) -> Self {
+        Self {
+            tot_time: 0,
+            successes: 0,
+            failures: 0,
+            prepared: Histogram::new(resolution_ms),
+            phases: BTreeMap::new(),
+            payments_rejected: 0,
+        }
+    }
+
+    fn print(&self, entity: u8) {
+        let total = self.successes.saturating_add(self.failures);
+        let average = self.tot_time.checked_div(self.successes).unwrap_or(0);
+        println!(
+            "Entity : {} average : {} milisecs, failure rate : {:.1}% ({} of {})",
+            entity_name(entity),
+            average,
+            100.0 * self.failures as f64 / total as f64,
+            self.failures,
+            total
+        );
+        println!("    prepared until commit : {}", self.prepared);
+        for (phase, histogram) in &self.phases {
+            println!("    {} : {}", phase_name(*phase), histogram);
+        }
+    }
+}
+
+/// How many payments went through each step of the two phase commit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Lifecycle {
+    started: u64,
+    prepared: u64,
+    committed: u64,
+    aborted: u64,
+}
+
+impl Lifecycle {
+    /// Payments started but neither committed nor aborted yet.
+    fn in_flight(&self) -> u64 {
+        self.started
+            .saturating_sub(self.committed)
+            .saturating_sub(self.aborted)
+    }
+}
+
+/// Aggregates saved by stats, so they survive a restart.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    tot_time: u64,
+    payments_count: u64,
+    payments_failed: u64,
+    payments: Histogram,
+    leader: Option<u8>,
+    entities: HashMap<u8, EntityStats>,
+    failures: Vec<(Cause, u64)>,
+    #[serde(default)]
+    lifecycle: Lifecycle,
+    #[serde(default)]
+    leader_changes: u64,
+    #[serde(default)]
+    slowest: Vec<SlowPayment>,
+}
+
+/// Asks for the current aggregates.
+#[derive(Debug, Message)]
+#[rtype(result = "StatsReport")]
+struct QueryReport;
+
+/// Asks stats to save a snapshot and write the time series row of the
+/// interval so far right away, before shutting down.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+struct Flush;
+
 struct Stats {
-    tot_time: u32,
-    payments_count: u32,
+    tot_time: u64,
+    payments_count: u64,
+    payments_failed: u64,
+    payments: Histogram,
+    /// Id of the AlGlobo replica that last reported being the leader.
+    leader: Option<u8>,
+    leader_changes: u64,
+    lifecycle: Lifecycle,
+    slowest: SlowestPayments,
+    failures: FailureBreakdown,
+    /// The last `RECENT_FAILURES` failures, most recent first.
+    recent_failures: VecDeque<RecentFailure>,
+    /// Recent payments, for the rolling `WINDOWS`.
+    windows: SlidingWindows,
+    /// How often the rolling windows are printed.
+    window_report_interval: Duration,
+    /// Whether the events applied come from the event log, so they happened
+    /// before stats started and are left out of the rolling windows.
+    rebuilding: bool,
+    resolution_ms: u64,
+    entities: HashMap<u8, EntityStats>,
+    /// Where snapshots are saved and how often, if they are.
+    snapshots: Option<(PathBuf, Duration)>,
+    event_log: Option<EventLog>,
+    /// Where a row is written and how often, if the time series is exported.
+    timeseries: Option<(TimeSeries, Duration)>,
 }
 
 impl Stats {
-    pub fn new() -> Self {
+    /// Latencies are kept in histograms with buckets of `resolution_ms`, and
+    /// the top failure causes are also reported over `failure_window`.
+    pub fn new(resolution_ms: u64, failure_window: Duration) -> Self {
         Self {
             tot_time: 0,
             payments_count: 0,
+            payments_failed: 0,
+            payments: Histogram::new(resolution_ms),
+            leader: None,
+            leader_changes: 0,
+            lifecycle: Lifecycle::default(),
+            slowest: SlowestPayments::new(DEFAULT_SLOWEST),
+            failures: FailureBreakdown::new(failure_window),
+            recent_failures: VecDeque::new(),
+            windows: SlidingWindows::new(resolution_ms, Instant::now()),
+            window_report_interval: Duration::from_secs(DEFAULT_WINDOW_REPORT_S),
+            rebuilding: false,
+            resolution_ms,
+            entities: HashMap::new(),
+            snapshots: None,
+            event_log: None,
+            timeseries: None,
         }
     }
-}
 
-impl Actor for Stats {
-    type Context = Context<Self>;
+    /// Saves a snapshot to `path` every `interval` and when stopped.
+    pub fn with_snapshots(mut self, path: PathBuf, interval: Duration) -> Self {
+        self.snapshots = Some((path, interval));
+        self
+    }
 
-    fn started(&mut self, _ctx: &mut Context<Self>) {
-        println!("[STATS] Iniciado");
+    /// Prints the rolling windows every `interval`.
+    pub fn with_window_reports(mut self, interval: Duration) -> Self {
+        self.window_report_interval = interval;
+        self
     }
 
-    fn stopped(&mut self, _ctx: &mut Context<Self>) {
-        println!("[STATS] Detenido");
+    fn print_windows(&self) {
+        let now = Instant::now();
+        for (label, window) in WINDOWS {
+            println!(
+                "Payments, last {} : {}",
+                label,
+                self.windows.summary(window, now)
+            );
+        }
     }
-}
 
-impl Handler<Event> for Stats {
-    type Result = ();
+    /// Keeps the `limit` slowest payments.
+    pub fn with_slowest(mut self, limit: usize) -> Self {
+        self.slowest = SlowestPayments::new(limit);
+        self
+    }
 
-    fn handle(&mut self, msg: Event, _ctx: &mut Context<Self>) -> Self::Result {
-        println!("[STATS] handle: {:?}", msg);
-        match msg {
-            Event::TxSuccess {
+    /// Appends every event received to `event_log`.
+    pub fn with_event_log(mut self, event_log: EventLog) -> Self {
+        self.event_log = Some(event_log);
+        self
+    }
+
+    /// Writes a row of the payments of the last `interval` to `timeseries`
+    /// every `interval` and when stopped.
+    pub fn with_timeseries(mut self, timeseries: TimeSeries, interval: Duration) -> Self {
+        self.timeseries = Some((timeseries, interval));
+        self
+    }
+
+    fn write_timeseries_row(&mut self) {
+        if let Some((timeseries, _)) = &mut self.timeseries {
+            if let Err(e) = timeseries.write_row(now_ms()) {
+                println!("[STATS] Could not write the time series: {}", e);
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            tot_time: self.tot_time,
+            payments_count: self.payments_count,
+            payments_failed: self.payments_failed,
+            payments: self.payments.clone(),
+            leader: self.leader,
+            entities: self.entities.clone(),
+            failures: self
+                .failures
+                .totals()
+                .into_iter()
+                .map(|(cause, total)| (cause.clone(), total))
+                .collect(),
+            lifecycle: self.lifecycle.clone(),
+            leader_changes: self.leader_changes,
+            slowest: self.slowest.slowest().to_vec(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.tot_time = snapshot.tot_time;
+        self.payments_count = snapshot.payments_count;
+        self.payments_failed = snapshot.payments_failed;
+        self.payments = snapshot.payments;
+        self.leader = snapshot.leader;
+        self.entities = snapshot.entities;
+        self.failures.restore(snapshot.failures);
+        self.lifecycle = snapshot.lifecycle;
+        self.leader_changes = snapshot.leader_changes;
+        self.slowest.restore(snapshot.slowest);
+    }
+
+    fn save_snapshot(&self) {
+        if let Some((path, _)) = &self.snapshots {
+            if let Err(e) = save_snapshot(path, &self.snapshot()) {
+                println!("[STATS] Could not save snapshot to {:?}: {}", path, e);
+            }
+        }
+    }
+
+    fn entity(&mut self, entity: u8) -> &mut EntityStats {
+        let resolution_ms = self.resolution_ms;
+        self.entities
+            .entry(entity)
+            .or_insert_with(|| EntityStats::new(resolution_ms))
+    }
+
+    fn entity_failed(&mut self, entity: u8, code: u8) {
+        let stats = self.entity(entity);
+        stats.failures = stats.failures.saturating_add(1);
+        stats.print(entity);
+        self.record_failure(entity_name(entity), failure_name(code));
+    }
+
+    fn record_failure(&mut self, source: &str, reason: &str) {
+        if self.rebuilding {
+            self.failures.record_past(source, reason);
+            return;
+        }
+        let now = Instant::now();
+        self.failures.record(source, reason, now);
+        println!(
+            "Top failure causes, last {}s : {}",
+            self.failures.window().as_secs(),
+            format_causes(&self.failures.top_recent(TOP_FAILURE_CAUSES, now))
+        );
+        println!(
+            "Top failure causes, overall : {}",
+            format_causes(&self.failures.top(TOP_FAILURE_CAUSES))
+        );
+    }
+
+    fn apply(&mut self, event: Event) {
+        println!("[STATS] handle: {:?}", event);
+        let transaction_id = event.transaction_id;
+        match &event.kind {
+            EventKind::TxFailure { reason, .. } | EventKind::PaymentFailed { reason, .. } => {
+                self.remember_failure(&event, reason)
+            }
+            EventKind::TxExpired { .. } => {
+                self.remember_failure(&event, failure_name(PREPARE_EXPIRED))
+            }
+            _ => {}
+        }
+        match event.kind {
+            EventKind::TxSuccess {
                 entity,
                 duration_ms,
             } => {
-                println!("Entity : {} took : {}", entity, duration_ms);
+                println!("Entity : {} took : {}", entity_name(entity), duration_ms);
+                let stats = self.entity(entity);
+                if add_sample(&mut stats.tot_time, &mut stats.successes, duration_ms) {
+                    stats.prepared.record(duration_ms as u64);
+                    stats.print(entity);
+                }
             }
-            Event::TxFailure { entity, reason } => {
-                println!("Entity : {} failed because : {}", entity, reason);
+            EventKind::TxFailure {
+                entity,
+                code,
+                reason,
+            } => {
+                println!(
+                    "Entity : {} failed because : {}",
+                    entity_name(entity),
+                    reason
+                );
+                self.entity_failed(entity, code);
             }
-            Event::PaymentSuccess { duration } => {
-                self.tot_time += duration;
-                self.payments_count += 1;
+            EventKind::PaymentSuccess { duration } => {
                 println!("Payment took : {}", duration);
+                if self.slowest.finish(
+                    transaction_id,
+                    event.source.to_string(),
+                    event.timestamp_ms,
+                    duration,
+                ) {
+                    println!(
+                        "Transaction {} is among the slowest payments",
+                        transaction_id
+                    );
+                }
+                if !add_sample(&mut self.tot_time, &mut self.payments_count, duration) {
+                    return;
+                }
+                self.payments.record(duration as u64);
+                if !self.rebuilding {
+                    self.windows.record_success(duration as u64, Instant::now());
+                }
+                if let Some((timeseries, _)) = &mut self.timeseries {
+                    timeseries.record_success(duration as u64);
+                }
                 println!(
                     "Payments average : {} milisecs",
                     self.tot_time / self.payments_count
                 );
+                println!("Payments latency : {}", self.payments);
+            }
+            EventKind::PhaseDuration {
+                entity,
+                phase,
+                duration_ms,
+            } => {
+                self.slowest.record_phase(
+                    transaction_id,
+                    PhaseTiming {
+                        entity: entity_name(entity).into(),
+                        phase: phase_name(phase).into(),
+                        duration_ms,
+                    },
+                );
+                if let Some((timeseries, _)) = &mut self.timeseries {
+                    timeseries.record_phase(entity, duration_ms as u64);
+                }
+                let resolution_ms = self.resolution_ms;
+                self.entity(entity)
+                    .phases
+                    .entry(phase)
+                    .or_insert_with(|| Histogram::new(resolution_ms))
+                    .record(duration_ms as u64);
+            }
+            EventKind::PaymentFailed { entity, reason } => {
+                self.slowest.forget(transaction_id);
+                self.payments_failed = self.payments_failed.saturating_add(1);
+                if !self.rebuilding {
+                    self.windows.record_failure(Instant::now());
+                }
+                if let Some((timeseries, _)) = &mut self.timeseries {
+                    timeseries.record_failure();
+                }
+                let stats = self.entity(entity);
+                stats.payments_rejected = stats.payments_rejected.saturating_add(1);
+                println!("Payment Failed because: {}", reason);
+                self.record_failure("payment", &reason);
+            }
+            EventKind::PaymentStarted => {
+                self.lifecycle.started = self.lifecycle.started.saturating_add(1);
             }
-            Event::PaymentFailed { reason } => {
-                println!("Payment Failed because: {}", reason)
+            EventKind::PaymentPrepared => {
+                self.lifecycle.prepared = self.lifecycle.prepared.saturating_add(1);
             }
+            EventKind::PaymentCommitted => {
+                self.lifecycle.committed = self.lifecycle.committed.saturating_add(1);
+                println!("Payments in flight : {}", self.lifecycle.in_flight());
+            }
+            EventKind::PaymentAborted => {
+                self.lifecycle.aborted = self.lifecycle.aborted.saturating_add(1);
+                println!("Payments in flight : {}", self.lifecycle.in_flight());
+            }
+            EventKind::LeaderChanged { leader } => {
+                println!("AlGlobo replica {} is the leader", leader);
+                self.leader = Some(leader);
+                self.leader_changes = self.leader_changes.saturating_add(1);
+            }
+            EventKind::TxExpired { entity } => {
+                println!(
+                    "Entity : {} gave up on expired transaction {}",
+                    entity_name(entity),
+                    transaction_id
+                );
+                self.entity_failed(entity, PREPARE_EXPIRED);
+            }
+        }
+    }
+
+    /// Applies the events of the event log, oldest first.
+    fn rebuild(&mut self, events: Vec<Event>) {
+        println!("[STATS] Rebuilding from {} logged events", events.len());
+        self.rebuilding = true;
+        for event in events {
+            self.apply(event);
+        }
+        self.rebuilding = false;
+    }
+
+    fn report(&self) -> StatsReport {
+        let now = Instant::now();
+        let mut entities: Vec<_> = self.entities.iter().collect();
+        entities.sort_by_key(|(entity, _)| **entity);
+        let mut failures = self.failures.totals();
+        failures.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        StatsReport {
+            payments: PaymentsReport {
+                succeeded: self.payments_count,
+                failed: self.payments_failed,
+                average_ms: self.tot_time.checked_div(self.payments_count),
+                latency: self.payments.percentiles(),
+                started: self.lifecycle.started,
+                prepared: self.lifecycle.prepared,
+                committed: self.lifecycle.committed,
+                aborted: self.lifecycle.aborted,
+                in_flight: self.lifecycle.in_flight(),
+            },
+            windows: WINDOWS
+                .iter()
+                .map(|(label, window)| {
+                    let summary = self.windows.summary(*window, now);
+                    WindowReport {
+                        window: label.to_string(),
+                        payments_per_second: summary.throughput,
+                        failure_rate: summary.failure_rate(),
+                        latency: summary.latency.percentiles(),
+                    }
+                })
+                .collect(),
+            entities: entities
+                .into_iter()
+                .map(|(entity, stats)| EntityReport {
+                    entity: entity_name(*entity).into(),
+                    committed: stats.successes,
+                    failed: stats.failures,
+                    average_ms: stats.tot_time.checked_div(stats.successes),
+                    payments_rejected: stats.payments_rejected,
+                    prepared: stats.prepared.percentiles(),
+                    phases: stats
+                        .phases
+                        .iter()
+                        .map(|(phase, histogram)| PhaseReport {
+                            phase: phase_name(*phase).into(),
+                            latency: histogram.percentiles(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            recent_failures: self.recent_failures.iter().cloned().collect(),
+            failures: failures
+                .into_iter()
+                .map(|(cause, count)| FailureReport {
+                    source: cause.source.clone(),
+                    reason: cause.reason.clone(),
+                    count,
+                })
+                .collect(),
+            leader: self.leader,
+            leader_changes: self.leader_changes,
+            slowest: self.slowest.slowest().to_vec(),
         }
     }
+
+    fn remember_failure(&mut self, event: &Event, reason: &str) {
+        self.recent_failures.push_front(RecentFailure {
+            transaction_id: event.transaction_id,
+            source: event.source.to_string(),
+            reason: reason.into(),
+            at_ms: event.timestamp_ms,
+        });
+        self.recent_failures.truncate(RECENT_FAILURES);
+    }
+
+    fn render_metrics(&self) -> String {
+        let mut page = MetricsPage::new();
+        page.family(
+            "alglobo_payments_total",
+            "counter",
+            "Payments processed by AlGlobo, by outcome.",
+        );
+        page.sample(
+            "alglobo_payments_total",
+            &[("outcome", "succeeded")],
+            self.payments_count,
+        );
+        page.sample(
+            "alglobo_payments_total",
+            &[("outcome", "failed")],
+            self.payments_failed,
+        );
+
+        page.family(
+            "alglobo_payment_lifecycle_total",
+            "counter",
+            "Payments that went through each step of the two phase commit.",
+        );
+        for (stage, count) in [
+            ("started", self.lifecycle.started),
+            ("prepared", self.lifecycle.prepared),
+            ("committed", self.lifecycle.committed),
+            ("aborted", self.lifecycle.aborted),
+        ] {
+            page.sample(
+                "alglobo_payment_lifecycle_total",
+                &[("stage", stage)],
+                count,
+            );
+        }
+        page.family(
+            "alglobo_payments_in_flight",
+            "gauge",
+            "Payments started but neither committed nor aborted yet.",
+        );
+        page.sample(
+            "alglobo_payments_in_flight",
+            &[],
+            self.lifecycle.in_flight(),
+        );
+
+        page.family(
+            "alglobo_payment_duration_milliseconds",
+            "histogram",
+            "End to end latency of the successful payments.",
+        );
+        page.histogram("alglobo_payment_duration_milliseconds", &[], &self.payments);
+
+        let mut entities: Vec<_> = self.entities.iter().collect();
+        entities.sort_by_key(|(entity, _)| **entity);
+        page.family(
+            "alglobo_entity_transactions_total",
+            "counter",
+            "Transactions reported by each entity, by outcome.",
+        );
+        for (entity, stats) in &entities {
+            let entity = entity_name(**entity);
+            page.sample(
+                "alglobo_entity_transactions_total",
+                &[("entity", entity), ("outcome", "committed")],
+                stats.successes,
+            );
+            page.sample(
+                "alglobo_entity_transactions_total",
+                &[("entity", entity), ("outcome", "failed")],
+                stats.failures,
+            );
+        }
+        page.family(
+            "alglobo_entity_payments_rejected_total",
+            "counter",
+            "Payments that failed because each entity rejected them.",
+        );
+        for (entity, stats) in &entities {
+            page.sample(
+                "alglobo_entity_payments_rejected_total",
+                &[("entity", entity_name(**entity))],
+                stats.payments_rejected,
+            );
+        }
+        page.family(
+            "alglobo_entity_prepared_duration_milliseconds",
+            "histogram",
+            "Time transactions spent prepared in each entity until committed.",
+        );
+        for (entity, stats) in &entities {
+            page.histogram(
+                "alglobo_entity_prepared_duration_milliseconds",
+                &[("entity", entity_name(**entity))],
+                &stats.prepared,
+            );
+        }
+        page.family(
+            "alglobo_entity_phase_duration_milliseconds",
+            "histogram",
+            "Round trip of each phase with each entity, as seen by AlGlobo.",
+        );
+        for (entity, stats) in &entities {
+            for (phase, histogram) in &stats.phases {
+                page.histogram(
+                    "alglobo_entity_phase_duration_milliseconds",
+                    &[
+                        ("entity", entity_name(**entity)),
+                        ("phase", phase_name(*phase)),
+                    ],
+                    histogram,
+                );
+            }
+        }
+
+        page.family(
+            "alglobo_failures_total",
+            "counter",
+            "Failures by the entity that failed, or payment, and reason.",
+        );
+        for (cause, count) in self.failures.totals() {
+            page.sample(
+                "alglobo_failures_total",
+                &[("source", &cause.source), ("reason", &cause.reason)],
+                count,
+            );
+        }
+
+        let now = Instant::now();
+        let summaries: Vec<_> = WINDOWS
+            .iter()
+            .map(|(label, window)| (*label, self.windows.summary(*window, now)))
+            .collect();
+        page.family(
+            "alglobo_window_payments_per_second",
+            "gauge",
+            "Payments processed per second over each rolling window.",
+        );
+        for (label, summary) in &summaries {
+            page.sample(
+                "alglobo_window_payments_per_second",
+                &[("window", label)],
+                summary.throughput,
+            );
+        }
+        page.family(
+            "alglobo_window_failure_ratio",
+            "gauge",
+            "Share of the payments that failed over each rolling window.",
+        );
+        for (label, summary) in &summaries {
+            page.sample(
+                "alglobo_window_failure_ratio",
+                &[("window", label)],
+                summary.failure_rate(),
+            );
+        }
+        page.family(
+            "alglobo_window_payment_duration_milliseconds",
+            "gauge",
+            "Latency percentiles of the successful payments over each rolling window.",
+        );
+        for (label, summary) in &summaries {
+            for percentile in PERCENTILES {
+                if let Some(latency) = summary.latency.percentile(percentile) {
+                    let quantile = (percentile / 100.0).to_string();
+                    page.sample(
+                        "alglobo_window_payment_duration_milliseconds",
+                        &[("window", label), ("quantile", &quantile)],
+                        latency,
+                    );
+                }
+            }
+        }
+
+        page.family(
+            "alglobo_leader_changes_total",
+            "counter",
+            "Times an AlGlobo replica reported becoming the leader.",
+        );
+        page.sample("alglobo_leader_changes_total", &[], self.leader_changes);
+
+        if let Some(leader) = self.leader {
+            page.family(
+                "alglobo_leader_id",
+                "gauge",
+                "Id of the AlGlobo replica that is currently the leader.",
+            );
+            page.sample("alglobo_leader_id", &[], leader as u64);
+        }
+        page.finish()
+    }
+}
+
+impl Actor for Stats {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        println!("[STATS] Iniciado");
+        if let Some((_, interval)) = &self.snapshots {
+            ctx.run_interval(*interval, |stats, _| stats.save_snapshot());
+        }
+        ctx.run_interval(self.window_report_interval, |stats, _| {
+            stats.print_windows()
+        });
+        if let Some((_, interval)) = &self.timeseries {
+            ctx.run_interval(*interval, |stats, _| stats.write_timeseries_row());
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Context<Self>) {
+        self.save_snapshot();
+        println!("[STATS] Detenido");
+    }
+}
+
+impl Handler<Flush> for Stats {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Flush, _ctx: &mut Context<Self>) -> Self::Result {
+        self.save_snapshot();
+        self.write_timeseries_row();
+    }
+}
+
+impl Handler<Event> for Stats {
+    type Result = ();
+
+    fn handle(&mut self, msg: Event, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(event_log) = &mut self.event_log {
+            if let Err(e) = event_log.append(&msg) {
+                println!("[STATS] Could not append to the event log: {}", e);
+            }
+        }
+        self.apply(msg);
+    }
+}
+
+impl Handler<QueryReport> for Stats {
+    type Result = MessageResult<QueryReport>;
+
+    fn handle(&mut self, _msg: QueryReport, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.report())
+    }
+}
+
+impl Handler<RenderMetrics> for Stats {
+    type Result = String;
+
+    fn handle(&mut self, _msg: RenderMetrics, _ctx: &mut Context<Self>) -> Self::Result {
+        self.render_metrics()
+    }
 }
 
 #[actix_rt::main]
@@ -69,22 +818,126 @@ async fn main() {
     let listener = TcpListener::bind("0.0.0.0:9996")
         .await
         .expect("Could not open port 9996");
-    let stats = Stats::new();
-    let addr = Arc::new(stats.start());
-    let mut handles = Vec::new();
-    while let Ok((stream, _)) = listener.accept().await {
-        let addr = addr.clone();
-        let mut protocol = EventProtocol::new(stream);
-        handles.push(actix_rt::spawn(async move {
-            loop {
-                if let Some(message) = protocol.recv_event().await {
-                    addr.send(message).await.unwrap();
-                }
+    let args = Args::from_env();
+    let snapshot_path: PathBuf = args
+        .get("snapshot")
+        .unwrap_or_else(|| DEFAULT_SNAPSHOT_PATH.into());
+    let bucket_ms: u64 = args.get("bucket-ms").unwrap_or(DEFAULT_BUCKET_MS);
+    if bucket_ms == 0 {
+        eprintln!("--bucket-ms must be greater than 0");
+        std::process::exit(2);
+    }
+    let mut stats = Stats::new(
+        bucket_ms,
+        Duration::from_secs(
+            args.get("failure-window-s")
+                .unwrap_or(DEFAULT_FAILURE_WINDOW_S),
+        ),
+    )
+    .with_window_reports(Duration::from_secs(
+        args.get("window-report-s")
+            .unwrap_or(DEFAULT_WINDOW_REPORT_S),
+    ))
+    .with_slowest(args.get("slowest").unwrap_or(DEFAULT_SLOWEST))
+    .with_snapshots(
+        snapshot_path.clone(),
+        Duration::from_secs(
+            args.get("snapshot-interval-s")
+                .unwrap_or(DEFAULT_SNAPSHOT_INTERVAL_S),
+        ),
+    );
+
+    let event_log_path: Option<PathBuf> = args.get("event-log");
+    if args.has_flag("rebuild") {
+        let path = event_log_path
+            .as_ref()
+            .expect("--rebuild needs the --event-log to rebuild from");
+        let events = EventLog::read(path)
+            .unwrap_or_else(|e| panic!("Could not read event log {:?}: {}", path, e));
+        stats.rebuild(events);
+    } else {
+        match load_snapshot::<Snapshot>(&snapshot_path) {
+            Ok(Some(snapshot)) if snapshot.version == SNAPSHOT_VERSION => {
+                println!("[STATS] Restored snapshot from {:?}", snapshot_path);
+                stats.restore(snapshot);
             }
-        }))
+            Ok(Some(snapshot)) => panic!(
+                "Snapshot {:?} has version {}, expected {}",
+                snapshot_path, snapshot.version, SNAPSHOT_VERSION
+            ),
+            Ok(None) => {}
+            Err(e) => panic!("Could not load snapshot {:?}: {}", snapshot_path, e),
+        }
+    }
+    if let Some(path) = &event_log_path {
+        let event_log = EventLog::open(path)
+            .unwrap_or_else(|e| panic!("Could not open event log {:?}: {}", path, e));
+        stats = stats.with_event_log(event_log);
     }
+    let timeseries_path: Option<PathBuf> = args.get("timeseries");
+    if let Some(path) = &timeseries_path {
+        let timeseries = TimeSeries::open(path, stats.resolution_ms)
+            .unwrap_or_else(|e| panic!("Could not open time series {:?}: {}", path, e));
+        let interval = Duration::from_secs(
+            args.get("timeseries-interval-s")
+                .unwrap_or(DEFAULT_TIMESERIES_INTERVAL_S),
+        );
+        println!(
+            "[STATS] Writing the time series to {:?} every {}s",
+            path,
+            interval.as_secs()
+        );
+        stats = stats.with_timeseries(timeseries, interval);
+    }
+    let addr = Arc::new(stats.start());
 
-    for handle in handles {
-        handle.await.ok();
+    let metrics_port = args.get("metrics-port").unwrap_or(DEFAULT_METRICS_PORT);
+    // Only reachable from this host, like the admin port of the entities.
+    let metrics_listener = TcpListener::bind(("127.0.0.1", metrics_port))
+        .await
+        .unwrap_or_else(|_| panic!("Could not open metrics port {}", metrics_port));
+    println!(
+        "[STATS] Metrics at http://127.0.0.1:{}/metrics",
+        metrics_port
+    );
+    actix_rt::spawn(serve_metrics(metrics_listener, (*addr).clone().recipient()));
+
+    let accept = async {
+        while let Ok((stream, _)) = listener.accept().await {
+            let addr = addr.clone();
+            let mut protocol = EventProtocol::new(stream);
+            actix_rt::spawn(async move {
+                // Until the client disconnects
+                while let Some(request) = protocol.recv_request().await {
+                    match request {
+                        Err(e) => {
+                            println!("[STATS] Closing connection after an invalid frame: {}", e);
+                            break;
+                        }
+                        Ok(StatsRequest::Event(event)) => {
+                            if addr.send(event).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(StatsRequest::Query) => {
+                            let report = match addr.send(QueryReport).await {
+                                Ok(report) => report,
+                                Err(_) => break,
+                            };
+                            if protocol.send_report(&report).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    };
+    tokio::select! {
+        _ = accept => {}
+        _ = wait_for_shutdown_signal() => println!("[STATS] Shutting down"),
+    }
+    if addr.send(Flush).await.is_err() {
+        println!("[STATS] Could not save the last snapshot and time series row");
     }
 }
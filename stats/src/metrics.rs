@@ -0,0 +1,121 @@
+use crate::histogram::Histogram;
+use actix::{Message, Recipient};
+use std::fmt::{Display, Write};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// Upper bounds, in milliseconds, of the buckets of every latency histogram.
+/// They are the same on every scrape so the series can be compared over time.
+const LATENCY_BUCKETS_MS: [u64; 12] =
+    [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000];
+
+/// Asks for every metric in the Prometheus text exposition format.
+#[derive(Debug, Message)]
+#[rtype(result = "String")]
+pub struct RenderMetrics;
+
+/// Builds a page in the Prometheus text exposition format.
+#[derive(Default)]
+pub struct MetricsPage {
+    page: String,
+}
+
+impl MetricsPage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a metric family, every sample of it must follow.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.page, "# HELP {} {}", name, help);
+        let _ = writeln!(self.page, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        let _ = writeln!(self.page, "{}{} {}", name, format_labels(labels), value);
+    }
+
+    /// Writes the buckets of `LATENCY_BUCKETS_MS`, sum and count of
+    /// `histogram`. The family must have been started with the `histogram`
+    /// type.
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let bucket = format!("{}_bucket", name);
+        for upper_bound in LATENCY_BUCKETS_MS {
+            let le = upper_bound.to_string();
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &le));
+            self.sample(
+                &bucket,
+                &bucket_labels,
+                histogram.count_at_most(upper_bound),
+            );
+        }
+        let mut bucket_labels = labels.to_vec();
+        bucket_labels.push(("le", "+Inf"));
+        self.sample(&bucket, &bucket_labels, histogram.count());
+        self.sample(&format!("{}_sum", name), labels, histogram.sum());
+        self.sample(&format!("{}_count", name), labels, histogram.count());
+    }
+
+    pub fn finish(self) -> String {
+        self.page
+    }
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Serves the metrics of `stats` over HTTP at `/metrics`, so they can be
+/// scraped by Prometheus or anything that speaks its text format.
+pub async fn serve_metrics(listener: TcpListener, stats: Recipient<RenderMetrics>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let stats = stats.clone();
+        actix_rt::spawn(async move {
+            let mut stream = BufReader::new(stream);
+            let mut request_line = String::new();
+            if stream.read_line(&mut request_line).await.is_err() {
+                return;
+            }
+            // Skip the headers, the request line is all we need.
+            let mut header = String::new();
+            while stream
+                .read_line(&mut header)
+                .await
+                .is_ok_and(|read| read > 2)
+            {
+                header.clear();
+            }
+
+            let path = request_line.split_whitespace().nth(1).unwrap_or("");
+            let (status, body) = if path == "/metrics" {
+                match stats.send(RenderMetrics).await {
+                    Ok(page) => ("200 OK", page),
+                    Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+                }
+            } else {
+                ("404 Not Found", "metrics are at /metrics\n".into())
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = stream.get_mut().write_all(response.as_bytes()).await;
+        });
+    }
+}
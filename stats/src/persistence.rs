@@ -0,0 +1,102 @@
+use helpers::event::Event;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Writes `snapshot` as JSON to `path`.
+///
+/// It is written to a temporary file that then replaces `path`, so a crash
+/// while saving leaves the previous snapshot intact.
+pub fn save_snapshot<T: Serialize>(path: &Path, snapshot: &T) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    serde_json::to_writer(&mut writer, snapshot)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+/// Reads the snapshot at `path`, or `None` if there is none yet.
+pub fn load_snapshot<T: DeserializeOwned>(path: &Path) -> io::Result<Option<T>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(serde_json::from_reader(BufReader::new(file))?))
+}
+
+/// Append-only log of every event received, framed like on the wire: a
+/// little endian u32 size followed by the event.
+pub struct EventLog {
+    file: File,
+}
+
+impl EventLog {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn append(&mut self, event: &Event) -> io::Result<()> {
+        let payload = event.to_bytes();
+        let mut frame = Vec::with_capacity(4 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend(payload);
+        // A single write, so a crash can only cut the last event short.
+        self.file.write_all(&frame)
+    }
+
+    /// Every event in the log at `path`, oldest first. An event cut short by
+    /// a crash at the end of the log is skipped.
+    pub fn read(path: &Path) -> io::Result<Vec<Event>> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let mut events = Vec::new();
+        let mut rest = bytes.as_slice();
+        while rest.len() >= 4 {
+            let size = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+            if size == 0 || rest.len() < 4 + size {
+                break;
+            }
+            events.push(Event::from_bytes(&rest[4..4 + size])?);
+            rest = &rest[4 + size..];
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helpers::event::{EventKind, EventSource, HOTEL_ENTITY};
+
+    #[test]
+    fn test_event_log_skips_truncated_event() {
+        let path = std::env::temp_dir().join(format!("stats-events-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut log = EventLog::open(&path).unwrap();
+        let payment = Event::new(
+            EventSource::Replica(30),
+            1,
+            EventKind::PaymentSuccess { duration: 12 },
+        );
+        let commit = Event::new(
+            EventSource::Entity(HOTEL_ENTITY),
+            1,
+            EventKind::TxSuccess {
+                entity: HOTEL_ENTITY,
+                duration_ms: 3,
+            },
+        );
+        log.append(&payment).unwrap();
+        log.append(&commit).unwrap();
+        // A crash in the middle of writing the last event.
+        log.file.write_all(&[9, 0, 0, 0, b'P']).unwrap();
+
+        let events = EventLog::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(events, vec![payment, commit]);
+    }
+}
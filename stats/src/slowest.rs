@@ -0,0 +1,111 @@
+use helpers::stats_query::{PhaseTiming, SlowPayment};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// Payments with phases recorded but not finished that are kept, so the
+/// ones abandoned by a crashed replica do not pile up.
+const MAX_PENDING: usize = 1024;
+
+/// The slowest successful payments, with the round trip of every phase
+/// AlGlobo ran for them.
+pub struct SlowestPayments {
+    limit: usize,
+    /// Slowest first.
+    slowest: Vec<SlowPayment>,
+    /// Phases of the payments still in flight, by transaction id.
+    pending: BTreeMap<u32, Vec<PhaseTiming>>,
+}
+
+impl SlowestPayments {
+    /// Keeps the `limit` slowest payments.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            slowest: Vec::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn record_phase(&mut self, transaction_id: u32, phase: PhaseTiming) {
+        self.pending.entry(transaction_id).or_default().push(phase);
+        if self.pending.len() > MAX_PENDING {
+            self.pending.pop_first();
+        }
+    }
+
+    /// Records a successful payment. Returns whether it is one of the
+    /// slowest.
+    pub fn finish(
+        &mut self,
+        transaction_id: u32,
+        source: String,
+        finished_at_ms: u64,
+        duration_ms: u32,
+    ) -> bool {
+        let phases = self.pending.remove(&transaction_id).unwrap_or_default();
+        let position = self
+            .slowest
+            .partition_point(|payment| payment.duration_ms >= duration_ms);
+        if position >= self.limit {
+            return false;
+        }
+        self.slowest.insert(
+            position,
+            SlowPayment {
+                transaction_id,
+                source,
+                finished_at_ms,
+                duration_ms,
+                phases,
+            },
+        );
+        self.slowest.truncate(self.limit);
+        true
+    }
+
+    /// Forgets the phases of a payment that will not finish successfully.
+    pub fn forget(&mut self, transaction_id: u32) {
+        self.pending.remove(&transaction_id);
+    }
+
+    pub fn slowest(&self) -> &[SlowPayment] {
+        &self.slowest
+    }
+
+    pub fn restore(&mut self, mut slowest: Vec<SlowPayment>) {
+        slowest.sort_by_key(|payment| Reverse(payment.duration_ms));
+        slowest.truncate(self.limit);
+        self.slowest = slowest;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phase(entity: &str, duration_ms: u32) -> PhaseTiming {
+        PhaseTiming {
+            entity: entity.into(),
+            phase: "prepare".into(),
+            duration_ms,
+        }
+    }
+
+    #[test]
+    fn test_keeps_slowest_with_their_phases() {
+        let mut payments = SlowestPayments::new(2);
+        payments.record_phase(1, phase("hotel", 3));
+        payments.record_phase(2, phase("bank", 40));
+        payments.record_phase(3, phase("airline", 1));
+        assert!(payments.finish(1, "alglobo-30".into(), 0, 10));
+        assert!(payments.finish(2, "alglobo-30".into(), 0, 50));
+        assert!(!payments.finish(3, "alglobo-30".into(), 0, 5));
+
+        let slowest = payments.slowest();
+        assert_eq!(slowest.len(), 2);
+        assert_eq!(slowest[0].transaction_id, 2);
+        assert_eq!(slowest[0].phases, vec![phase("bank", 40)]);
+        assert_eq!(slowest[1].transaction_id, 1);
+        assert!(payments.pending.is_empty());
+    }
+}
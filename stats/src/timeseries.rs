@@ -0,0 +1,134 @@
+use crate::histogram::Histogram;
+use csv::{Writer, WriterBuilder};
+use helpers::event::{entity_name, format_utc, AIRLINE_ENTITY, BANK_ENTITY, HOTEL_ENTITY};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// Entities with latency columns, always all of them so that every row has
+/// the same columns.
+const ENTITIES: [u8; 3] = [HOTEL_ENTITY, AIRLINE_ENTITY, BANK_ENTITY];
+
+/// Payments of each interval written as a row of a CSV file: when the
+/// interval ended, the successes and failures, the p50 and p95 of the
+/// successful payments and of the round trip of the phases with each entity.
+///
+/// Rows are appended, so restarts keep adding to the same series.
+pub struct TimeSeries {
+    writer: Writer<File>,
+    resolution_ms: u64,
+    successes: u64,
+    failures: u64,
+    payments: Histogram,
+    /// Round trip of every phase with each entity, by entity.
+    entities: BTreeMap<u8, Histogram>,
+}
+
+impl TimeSeries {
+    /// Appends to the file at `path`, writing the header first if it is new.
+    pub fn open(path: &Path, resolution_ms: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut writer = WriterBuilder::new().from_writer(file);
+        if is_new {
+            let mut header: Vec<String> =
+                ["timestamp", "successes", "failures", "p50_ms", "p95_ms"]
+                    .iter()
+                    .map(|column| column.to_string())
+                    .collect();
+            for entity in ENTITIES {
+                header.push(format!("{}_p50_ms", entity_name(entity)));
+                header.push(format!("{}_p95_ms", entity_name(entity)));
+            }
+            writer.write_record(&header)?;
+            writer.flush()?;
+        }
+        Ok(Self {
+            writer,
+            resolution_ms,
+            successes: 0,
+            failures: 0,
+            payments: Histogram::new(resolution_ms),
+            entities: BTreeMap::new(),
+        })
+    }
+
+    pub fn record_success(&mut self, duration_ms: u64) {
+        self.successes += 1;
+        self.payments.record(duration_ms);
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failures += 1;
+    }
+
+    pub fn record_phase(&mut self, entity: u8, duration_ms: u64) {
+        let resolution_ms = self.resolution_ms;
+        self.entities
+            .entry(entity)
+            .or_insert_with(|| Histogram::new(resolution_ms))
+            .record(duration_ms);
+    }
+
+    /// Writes the row of the interval that ends at `timestamp_ms` and starts
+    /// the next one. Latencies without samples are left empty.
+    pub fn write_row(&mut self, timestamp_ms: u64) -> io::Result<()> {
+        let latency = |histogram: Option<&Histogram>, percentile: f64| {
+            histogram
+                .and_then(|histogram| histogram.percentile(percentile))
+                .map_or_else(String::new, |latency| latency.to_string())
+        };
+        // A date and time that spreadsheets can read.
+        let mut row = vec![
+            format_utc(timestamp_ms),
+            self.successes.to_string(),
+            self.failures.to_string(),
+            latency(Some(&self.payments), 50.0),
+            latency(Some(&self.payments), 95.0),
+        ];
+        for entity in ENTITIES {
+            row.push(latency(self.entities.get(&entity), 50.0));
+            row.push(latency(self.entities.get(&entity), 95.0));
+        }
+        self.writer.write_record(&row)?;
+        self.writer.flush()?;
+
+        self.successes = 0;
+        self.failures = 0;
+        self.payments = Histogram::new(self.resolution_ms);
+        self.entities.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_writes_one_row_per_interval() {
+        let path = std::env::temp_dir().join(format!("stats-series-{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut series = TimeSeries::open(&path, 10).unwrap();
+        series.record_success(120);
+        series.record_failure();
+        series.record_phase(BANK_ENTITY, 35);
+        series.write_row(951_827_696_000).unwrap();
+        series.write_row(951_827_756_000).unwrap();
+        // Reopening keeps the rows and does not repeat the header.
+        drop(series);
+        TimeSeries::open(&path, 10).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "timestamp,successes,failures,p50_ms,p95_ms,hotel_p50_ms,hotel_p95_ms,\
+             airline_p50_ms,airline_p95_ms,bank_p50_ms,bank_p95_ms\n\
+             2000-02-29 12:34:56,1,1,120,120,,,,,35,35\n\
+             2000-02-29 12:35:56,0,0,,,,,,,,\n"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::histogram::Histogram;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Rolling windows reported by stats, with the label used for each.
+pub const WINDOWS: [(&str, Duration); 3] = [
+    ("1m", Duration::from_secs(60)),
+    ("5m", Duration::from_secs(5 * 60)),
+    ("15m", Duration::from_secs(15 * 60)),
+];
+
+/// Payments that finished within one second.
+struct Second {
+    /// Seconds since the windows started.
+    at: u64,
+    succeeded: u64,
+    failed: u64,
+    latency: Histogram,
+}
+
+/// Payments over one of the rolling windows.
+pub struct WindowSummary {
+    pub succeeded: u64,
+    pub failed: u64,
+    /// Payments, successful or not, per second.
+    pub throughput: f64,
+    pub latency: Histogram,
+}
+
+impl WindowSummary {
+    /// Share of the payments that failed, from 0 to 1.
+    pub fn failure_rate(&self) -> f64 {
+        let total = self.succeeded + self.failed;
+        if total == 0 {
+            0.0
+        } else {
+            self.failed as f64 / total as f64
+        }
+    }
+}
+
+impl fmt::Display for WindowSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2} payments/s, failure rate : {:.1}%, latency : {}",
+            self.throughput,
+            100.0 * self.failure_rate(),
+            self.latency
+        )
+    }
+}
+
+/// Payments counted per second over the longest window, so throughput,
+/// failure rate and latency can be computed over any of the `WINDOWS`.
+pub struct SlidingWindows {
+    resolution_ms: u64,
+    started: Instant,
+    seconds: VecDeque<Second>,
+}
+
+impl SlidingWindows {
+    /// Latencies are kept in histograms with buckets of `resolution_ms`.
+    pub fn new(resolution_ms: u64, started: Instant) -> Self {
+        Self {
+            resolution_ms,
+            started,
+            seconds: VecDeque::new(),
+        }
+    }
+
+    pub fn record_success(&mut self, latency_ms: u64, now: Instant) {
+        let second = self.second(now);
+        second.succeeded += 1;
+        second.latency.record(latency_ms);
+    }
+
+    pub fn record_failure(&mut self, now: Instant) {
+        self.second(now).failed += 1;
+    }
+
+    /// The second `now` falls in, forgetting the ones no window reaches.
+    fn second(&mut self, now: Instant) -> &mut Second {
+        let at = now.saturating_duration_since(self.started).as_secs();
+        let longest = WINDOWS[WINDOWS.len() - 1].1.as_secs();
+        while matches!(self.seconds.front(), Some(second) if second.at + longest <= at) {
+            self.seconds.pop_front();
+        }
+        if self.seconds.back().is_none_or(|second| second.at != at) {
+            self.seconds.push_back(Second {
+                at,
+                succeeded: 0,
+                failed: 0,
+                latency: Histogram::new(self.resolution_ms),
+            });
+        }
+        self.seconds.back_mut().unwrap()
+    }
+
+    /// Payments within the `window` that ends at `now`. Until stats has been
+    /// up for a whole window, throughput is over the time it has been up.
+    pub fn summary(&self, window: Duration, now: Instant) -> WindowSummary {
+        let elapsed = now.saturating_duration_since(self.started);
+        let at = elapsed.as_secs();
+        let mut summary = WindowSummary {
+            succeeded: 0,
+            failed: 0,
+            throughput: 0.0,
+            latency: Histogram::new(self.resolution_ms),
+        };
+        for second in &self.seconds {
+            if second.at + window.as_secs() <= at {
+                continue;
+            }
+            summary.succeeded += second.succeeded;
+            summary.failed += second.failed;
+            summary.latency.merge(&second.latency);
+        }
+        let seconds = window.min(elapsed).as_secs_f64().max(1.0);
+        summary.throughput = (summary.succeeded + summary.failed) as f64 / seconds;
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_old_payments_leave_the_short_window() {
+        let started = Instant::now();
+        let mut windows = SlidingWindows::new(1, started);
+        for _ in 0..60 {
+            windows.record_success(500, started);
+        }
+        let now = started + Duration::from_secs(240);
+        windows.record_success(5, now);
+        windows.record_failure(now);
+
+        let minute = windows.summary(WINDOWS[0].1, now);
+        assert_eq!((minute.succeeded, minute.failed), (1, 1));
+        assert_eq!(minute.failure_rate(), 0.5);
+        assert_eq!(minute.latency.percentile(99.0), Some(5));
+        assert!((minute.throughput - 2.0 / 60.0).abs() < 1e-9);
+
+        let five_minutes = windows.summary(WINDOWS[1].1, now);
+        assert_eq!(five_minutes.succeeded, 61);
+        assert_eq!(five_minutes.latency.percentile(50.0), Some(500));
+        // Stats has only been up for 4 of the 5 minutes.
+        assert!((five_minutes.throughput - 62.0 / 240.0).abs() < 1e-9);
+    }
+}